@@ -28,6 +28,7 @@ use tokio::{
     sync::{
         mpsc,
         oneshot,
+        Notify,
     },
 };
 
@@ -49,6 +50,7 @@ const HEARTBEAT_TYPE: StreamType = StreamType::clamp(0xFFFFFFFF);
 pub struct Heartbeat<S> {
     typ: StreamType,
     inner: S,
+    stop: Arc<Notify>,
 }
 
 /// Controller for the heartbeat task.
@@ -97,6 +99,7 @@ where
         let mut hb = Heartbeat {
             typ: HEARTBEAT_TYPE,
             inner: sess,
+            stop: Default::default(),
         };
 
         let (dtx, drx) = mpsc::channel(1);
@@ -115,7 +118,8 @@ where
             .await
             .map_err(|_| io::ErrorKind::ConnectionReset)?;
 
-        ctl.start_requester(stream, drx, mtx).await?;
+        ctl.start_requester(stream, drx, mtx, hb.stop.clone())
+            .await?;
         ctl.start_check(mrx, cfg.callback)?;
 
         Ok((hb, ctl))
@@ -134,6 +138,8 @@ impl HeartbeatCtl {
     }
 
     /// Change the heartbeat interval.
+    ///
+    /// Takes effect after the next heartbeat.
     pub fn set_interval(&self, interval: Duration) {
         self.durations
             .0
@@ -201,59 +207,78 @@ impl HeartbeatCtl {
         mut stream: TypedStream,
         mut on_demand: mpsc::Receiver<oneshot::Sender<Duration>>,
         mark: mpsc::Sender<Duration>,
+        stop: Arc<Notify>,
     ) -> Result<(), io::Error> {
-        let (interval, _) = self.get_durations();
+        let (mut interval, _) = self.get_durations();
         let mut ticker = tokio::time::interval(interval);
+        let durations = self.durations.clone();
 
-        tokio::spawn(
-            async move {
-                loop {
-                    let mut resp_chan: Option<oneshot::Sender<Duration>> = None;
+        let requester = async move {
+            loop {
+                let mut resp_chan: Option<oneshot::Sender<Duration>> = None;
+
+                // Pick up any interval changes made via the controller.
+                let (new_interval, _) = get_durations(&durations);
+                if new_interval != interval {
+                    interval = new_interval;
+                    ticker =
+                        tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+                }
 
-                    select! {
-                        // If on_demand is closed, this will return None
-                        // immediately. In that case, wait on the next tick instead.
-                        c = on_demand.recv() => if c.is_none() {
-                            ticker.tick().await;
-                        } else {
-                            resp_chan = c;
-                        },
-                        _ = ticker.tick() => {},
-                    }
+                select! {
+                    // If on_demand is closed, this will return None
+                    // immediately. In that case, wait on the next tick instead.
+                    c = on_demand.recv() => if c.is_none() {
+                        ticker.tick().await;
+                    } else {
+                        resp_chan = c;
+                    },
+                    _ = ticker.tick() => {},
+                }
 
-                    tracing::debug!("sending heartbeat");
+                tracing::debug!("sending heartbeat");
 
-                    let start = std::time::Instant::now();
-                    let id: i32 = rand::random();
+                let start = std::time::Instant::now();
+                let id: i32 = rand::random();
+
+                if stream.write_all(&id.to_be_bytes()[..]).await.is_err() {
+                    return;
+                }
 
-                    if stream.write_all(&id.to_be_bytes()[..]).await.is_err() {
-                        return;
-                    }
+                let mut resp_bytes = [0u8; 4];
 
-                    let mut resp_bytes = [0u8; 4];
+                tracing::debug!("waiting for response");
 
-                    tracing::debug!("waiting for response");
+                if stream.read_exact(&mut resp_bytes[..]).await.is_err() {
+                    tracing::debug!("error reading response");
+                    return;
+                }
 
-                    if stream.read_exact(&mut resp_bytes[..]).await.is_err() {
-                        tracing::debug!("error reading response");
-                        return;
-                    }
+                tracing::debug!("got response");
 
-                    tracing::debug!("got response");
+                let resp_id = i32::from_be_bytes(resp_bytes);
 
-                    let resp_id = i32::from_be_bytes(resp_bytes);
+                if id != resp_id {
+                    return;
+                }
 
-                    if id != resp_id {
-                        return;
-                    }
+                let latency = std::time::Instant::now() - start;
 
-                    let latency = std::time::Instant::now() - start;
+                if let Some(resp_chan) = resp_chan {
+                    let _ = resp_chan.send(latency);
+                } else {
+                    let _ = mark.send(latency).await;
+                }
+            }
+        };
 
-                    if let Some(resp_chan) = resp_chan {
-                        let _ = resp_chan.send(latency);
-                    } else {
-                        let _ = mark.send(latency).await;
-                    }
+        // Exiting the requester drops the mark sender, which stops the check
+        // task as well.
+        tokio::spawn(
+            async move {
+                select! {
+                    _ = requester => {},
+                    _ = stop.notified() => {},
                 }
             }
             .then(|_| async move {
@@ -318,6 +343,11 @@ where
 
         self.inner.open_typed(typ).await
     }
+
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        self.stop.notify_one();
+        self.inner.close(error, message).await
+    }
 }
 
 impl<S> TypedSession for Heartbeat<S>
@@ -333,8 +363,16 @@ where
         let typ = self.typ;
         let (open, accept) = self.inner.split_typed();
         (
-            Heartbeat { typ, inner: open },
-            Heartbeat { typ, inner: accept },
+            Heartbeat {
+                typ,
+                inner: open,
+                stop: self.stop.clone(),
+            },
+            Heartbeat {
+                typ,
+                inner: accept,
+                stop: self.stop,
+            },
         )
     }
 }
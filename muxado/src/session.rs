@@ -119,6 +119,8 @@ where
 
         let (accept_tx, accept_rx) = mpsc::channel(accept_queue_size);
         let (open_tx, open_rx) = mpsc::channel(512);
+        let (close_tx, close_rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
 
         let manager = StreamManager::new(stream_limit, client);
         let sys_tx = manager.sys_sender();
@@ -140,14 +142,19 @@ where
             io: io_tx,
             manager: m2,
             open_reqs: open_rx,
+            close_reqs: close_rx,
+            _shutdown: shutdown_tx,
         };
 
-        tokio::spawn(read_task.run());
+        tokio::spawn(read_task.run(shutdown_rx));
         tokio::spawn(write_task.run());
 
         MuxadoSession {
             incoming: MuxadoAccept(accept_rx),
-            outgoing: MuxadoOpen(open_tx),
+            outgoing: MuxadoOpen {
+                open_reqs: open_tx,
+                close_reqs: close_tx,
+            },
         }
     }
 }
@@ -226,7 +233,8 @@ where
             // system channel.
             HeaderType::GoAway => {
                 if let Body::GoAway { error, .. } = frame.body {
-                    self.manager.go_away(error).await;
+                    debug!(error = display(error), "remote sent goaway");
+                    self.manager.go_away(Error::RemoteGoneAway).await;
                     return Err(Error::RemoteGoneAway);
                 }
 
@@ -247,11 +255,19 @@ where
     }
 
     // The actual read/process loop
-    #[instrument(level = "trace", skip(self))]
-    async fn run(mut self) -> Result<(), Error> {
+    //
+    // Also stops once the writer task exits, since the session can't make
+    // progress without it. This is what ends the session after a local close.
+    #[instrument(level = "trace", skip(self, shutdown))]
+    async fn run(mut self, shutdown: oneshot::Receiver<()>) -> Result<(), Error> {
+        let mut shutdown = shutdown.fuse();
         let _e: Result<(), _> = async {
             loop {
-                match self.io.try_next().await {
+                let frame = select! {
+                    frame = self.io.try_next().fuse() => frame,
+                    _ = shutdown => return Err(Error::SessionClosed),
+                };
+                match frame {
                     Ok(Some(frame)) => self.handle_frame(frame).await?,
                     Ok(None) | Err(_) => {
                         return Err(Error::SessionClosed);
@@ -273,7 +289,16 @@ struct Writer<W> {
     manager: SharedStreamManager,
     window: usize,
     open_reqs: mpsc::Receiver<oneshot::Sender<Result<Stream, Error>>>,
+    close_reqs: mpsc::Receiver<CloseReq>,
     io: W,
+    // Dropped when the writer exits to signal the reader to stop.
+    _shutdown: oneshot::Sender<()>,
+}
+
+struct CloseReq {
+    error: Error,
+    message: String,
+    done: oneshot::Sender<()>,
 }
 
 impl<W> Writer<W>
@@ -303,6 +328,23 @@ where
                         let _ = resp_tx.send(res.map(move |_| stream));
                     }
                 },
+                // A local close was requested. Let the remote know that we're
+                // going away, close the underlying stream, and shut down all
+                // of our streams.
+                req = self.close_reqs.next() => {
+                    if let Some(CloseReq { error, message, done }) = req {
+                        let last_stream_id = self.manager.lock().await.last_remote_id();
+                        debug!(error = display(error), message, "sending goaway");
+                        let _ = self
+                            .io
+                            .send(Frame::goaway(last_stream_id, error, message.into()))
+                            .await;
+                        let _ = self.io.close().await;
+                        self.manager.go_away(Error::SessionClosed).await;
+                        let _ = done.send(());
+                        return Ok(());
+                    }
+                },
                 // All senders have been dropped - exit.
                 complete => {
                     return Ok(());
@@ -337,10 +379,26 @@ pub trait Accept {
 pub trait Open {
     /// Open a new stream.
     async fn open(&mut self) -> Result<Stream, Error>;
+
+    /// Close the session, sending a GOAWAY with the provided error and
+    /// message to the remote.
+    ///
+    /// All open streams will be closed. Resolves once the GOAWAY has been
+    /// sent and the underlying stream closed.
+    ///
+    /// The default implementation doesn't support closing, and returns
+    /// [Error::Internal].
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        let _ = (error, message);
+        Err(Error::Internal)
+    }
 }
 
 /// The [Open] half of a muxado session.
-pub struct MuxadoOpen(mpsc::Sender<oneshot::Sender<Result<Stream, Error>>>);
+pub struct MuxadoOpen {
+    open_reqs: mpsc::Sender<oneshot::Sender<Result<Stream, Error>>>,
+    close_reqs: mpsc::Sender<CloseReq>,
+}
 /// The [Accept] half of a muxado session.
 pub struct MuxadoAccept(mpsc::Receiver<Stream>);
 
@@ -356,7 +414,7 @@ impl Open for MuxadoOpen {
     async fn open(&mut self) -> Result<Stream, Error> {
         let (resp_tx, resp_rx) = oneshot::channel();
 
-        self.0
+        self.open_reqs
             .send(resp_tx)
             .await
             .map_err(|_| Error::SessionClosed)?;
@@ -366,6 +424,21 @@ impl Open for MuxadoOpen {
             .map_err(|_| Error::SessionClosed)
             .and_then(|r| r)
     }
+
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        let (done, done_rx) = oneshot::channel();
+
+        self.close_reqs
+            .send(CloseReq {
+                error,
+                message,
+                done,
+            })
+            .await
+            .map_err(|_| Error::SessionClosed)?;
+
+        done_rx.await.map_err(|_| Error::SessionClosed)
+    }
 }
 
 /// The base muxado [Session] implementation.
@@ -389,6 +462,10 @@ impl Open for MuxadoSession {
     async fn open(&mut self) -> Result<Stream, Error> {
         self.outgoing.open().await
     }
+
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        self.outgoing.close(error, message).await
+    }
 }
 
 impl Session for MuxadoSession {
@@ -439,4 +516,34 @@ mod test {
 
         assert_eq!(b"Hello, world!", &*buf,);
     }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (left, right) = io::duplex(512);
+        let mut server = SessionBuilder::new(left).server().start();
+        let mut client = SessionBuilder::new(right).client().start();
+
+        let mut stream = client.open().await.expect("open stream");
+        stream.write_all(b"hello").await.expect("write to stream");
+        let mut remote = server.accept().await.expect("accept stream");
+
+        client
+            .close(Error::None, "bye".into())
+            .await
+            .expect("close session");
+
+        // The remote sees the goaway, which ends its session.
+        assert!(server.accept().await.is_none());
+        let mut buf = Vec::new();
+        let _ = remote.read_to_end(&mut buf).await;
+
+        // Our streams are closed, and so is the session.
+        assert!(stream.write_all(b"world").await.is_err());
+        assert_eq!(Err(Error::SessionClosed), client.open().await.map(drop));
+        assert!(client.accept().await.is_none());
+        assert_eq!(
+            Err(Error::SessionClosed),
+            client.close(Error::None, "bye".into()).await
+        );
+    }
 }
@@ -14,7 +14,10 @@ use bytes::BytesMut;
 use futures::{
     channel::mpsc,
     ready,
-    sink::Sink,
+    sink::{
+        Sink,
+        SinkExt,
+    },
     stream::Stream as StreamT,
 };
 use pin_project::pin_project;
@@ -99,6 +102,20 @@ impl Stream {
         }
     }
 
+    /// Reset the stream, abandoning it and telling the remote to stop sending
+    /// on it.
+    ///
+    /// Any further writes to the stream will fail.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn reset(&mut self, error: Error) -> Result<(), Error> {
+        self.write_closed = Some(error);
+        // If we never sent anything, the remote doesn't know about the stream.
+        if self.needs_syn {
+            return Ok(());
+        }
+        self.fout.send(Body::Rst(error).into()).await
+    }
+
     #[instrument(level = "trace", skip_all)]
     fn poll_recv_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame>> {
         let mut this = self.project();
@@ -326,7 +343,11 @@ impl AsyncWrite for Stream {
 pub mod test {
     use std::time::Duration;
 
-    use futures::channel::mpsc;
+    use futures::{
+        channel::mpsc,
+        FutureExt,
+        StreamExt,
+    };
     use tokio::{
         io::{
             AsyncReadExt,
@@ -390,6 +411,33 @@ pub mod test {
 
         assert!(rx.try_next().unwrap().unwrap().is_fin());
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reset() {
+        let (_tx, stream_rx) = mpsc::channel(512);
+        let (stream_tx, mut rx) = mpsc::channel(512);
+        let stream_tx = StreamSender::wrap(stream_tx);
+
+        let mut stream = Stream::new(stream_tx, stream_rx, 512, true);
+
+        // Nothing to reset if the remote hasn't heard of the stream yet.
+        stream.reset(Error::StreamCancelled).await.unwrap();
+        assert!(rx.next().now_or_never().is_none());
+
+        let (_tx, stream_rx) = mpsc::channel(512);
+        let (stream_tx, mut rx) = mpsc::channel(512);
+        let stream_tx = StreamSender::wrap(stream_tx);
+
+        let mut stream = Stream::new(stream_tx, stream_rx, 512, false);
+
+        stream.reset(Error::StreamCancelled).await.unwrap();
+        assert_eq!(
+            rx.next().now_or_never().unwrap().unwrap(),
+            Body::Rst(Error::StreamCancelled).into()
+        );
+        assert!(stream.write_all(b"hello").await.is_err());
+    }
 }
 
 #[pin_project::pinned_drop]
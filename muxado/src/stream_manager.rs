@@ -133,13 +133,17 @@ impl StreamManager {
         )
     }
 
-    pub fn go_away(&mut self, _error: Error) {
+    pub fn go_away(&mut self, error: Error) {
         self.sent_away = true;
         for (_id, handle) in self.streams.drain() {
-            handle.sink_closer.close_with(Error::RemoteGoneAway);
+            handle.sink_closer.close_with(error);
         }
     }
 
+    pub fn last_remote_id(&self) -> StreamID {
+        self.last_remote_id
+    }
+
     pub fn sys_sender(&self) -> mpsc::Sender<Frame> {
         self.sys_tx.clone()
     }
@@ -218,7 +222,8 @@ impl StreamT for StreamManager {
                 handle.window += *inc as usize;
             }
 
-            if frame.is_fin() {
+            // A reset ends the stream just as well as a fin.
+            if frame.is_fin() || matches!(frame.body, Body::Rst(_)) {
                 debug!(stream_id = debug(id), "setting needs_fin to false");
                 handle.needs_fin = false;
             }
@@ -322,6 +327,8 @@ impl SharedStreamManager {
                 handle.sink_closer.close_with(err);
                 handle.data_write_closed = true;
                 handle.needs_fin = false;
+                // The remote won't be sending anything else either.
+                handle.to_stream.close_channel();
                 return Ok(()).into();
             }
 
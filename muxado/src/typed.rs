@@ -118,6 +118,16 @@ pub trait TypedAccept {
 pub trait TypedOpen {
     /// Open a typed stream with the given type.
     async fn open_typed(&mut self, typ: StreamType) -> Result<TypedStream, Error>;
+
+    /// Close the session, sending a GOAWAY with the provided error and
+    /// message to the remote.
+    ///
+    /// The default implementation doesn't support closing, and returns
+    /// [Error::Internal].
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        let _ = (error, message);
+        Err(Error::Internal)
+    }
 }
 
 #[async_trait]
@@ -158,6 +168,10 @@ where
 
         Ok(TypedStream { inner: stream, typ })
     }
+
+    async fn close(&mut self, error: Error, message: String) -> Result<(), Error> {
+        self.inner.close(error, message).await
+    }
 }
 
 impl<S> TypedSession for Typed<S>
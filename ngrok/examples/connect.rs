@@ -51,7 +51,7 @@ fn handle_tunnel(mut tunnel: impl UrlTunnel, sess: ngrok::Session) {
             };
 
             let sess = sess.clone();
-            let id: String = tunnel.id().into();
+            let id = tunnel.id();
 
             tokio::spawn(async move {
                 info!("accepted connection: {:?}", stream.remote_addr());
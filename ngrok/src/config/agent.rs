@@ -0,0 +1,749 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_yaml::{
+    Mapping,
+    Value,
+};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    config::{
+        HttpTunnelBuilder,
+        LabeledTunnelBuilder,
+        OauthOptions,
+        OidcOptions,
+        ProxyProto,
+        Scheme,
+        TcpTunnelBuilder,
+        TlsTunnelBuilder,
+    },
+    session::SessionBuilder,
+    Session,
+};
+
+// Top-level keys that only affect the ngrok agent process, and have no
+// equivalent in the SDK.
+const AGENT_ONLY_KEYS: &[&str] = &[
+    "api_key",
+    "console_ui",
+    "console_ui_color",
+    "dns_resolver_ips",
+    "inspect_db_size",
+    "log",
+    "log_format",
+    "log_level",
+    "update",
+    "update_channel",
+    "update_check",
+    "web_addr",
+    "web_allow_hosts",
+];
+
+/// Errors arising when loading an ngrok agent config file.
+#[derive(Error, Debug)]
+pub enum AgentConfigError {
+    /// The config file, or a file that it refers to, couldn't be read.
+    #[error("failed to read {path}")]
+    Read {
+        /// The path of the file.
+        path: String,
+        /// The error that occurred when reading it.
+        #[source]
+        error: io::Error,
+    },
+    /// The config file isn't valid YAML, or a key has the wrong type.
+    #[error("invalid agent config")]
+    Parse(#[from] serde_yaml::Error),
+    /// The config file isn't in the version 2 format.
+    #[error("unsupported agent config version: {0}")]
+    UnsupportedVersion(String),
+    /// A key isn't supported by the SDK, or doesn't apply to its tunnel's
+    /// protocol.
+    #[error("{section}: unsupported key `{key}`")]
+    UnsupportedKey {
+        /// Where the key appeared, e.g. `tunnels.website`.
+        section: String,
+        /// The key.
+        key: String,
+    },
+    /// A key has a value that the SDK doesn't understand.
+    #[error("{section}: invalid `{key}`: {reason}")]
+    InvalidValue {
+        /// Where the key appeared, e.g. `tunnels.website`.
+        section: String,
+        /// The key.
+        key: String,
+        /// Why the value is invalid.
+        reason: String,
+    },
+}
+
+/// An ngrok agent config file, in the version 2 format used by the ngrok
+/// agent.
+///
+/// The session-level keys `authtoken`, `server_addr`, `region`, `root_cas`,
+/// `heartbeat_interval`, `heartbeat_tolerance`, `metadata` and `proxy_url` are
+/// applied to a [SessionBuilder] by [AgentConfig::apply]. Keys that only
+/// affect the agent process, such as `web_addr` or `log`, are ignored.
+#[derive(Debug, Clone)]
+pub struct AgentConfig {
+    session: SessionConfig,
+    tunnels: Mapping,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct SessionConfig {
+    version: Option<Value>,
+    authtoken: Option<String>,
+    server_addr: Option<String>,
+    region: Option<String>,
+    root_cas: Option<String>,
+    heartbeat_interval: Option<String>,
+    heartbeat_tolerance: Option<String>,
+    metadata: Option<String>,
+    proxy_url: Option<String>,
+    #[serde(default)]
+    tunnels: Mapping,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+/// A tunnel defined in an [AgentConfig].
+#[derive(Clone)]
+pub struct AgentTunnel {
+    /// The name of the tunnel in the config file.
+    pub name: String,
+    /// The local address that the tunnel's connections should be forwarded
+    /// to, if one was given.
+    pub addr: Option<String>,
+    /// The builder for the tunnel.
+    pub builder: AgentTunnelBuilder,
+}
+
+/// The builder for an [AgentTunnel], according to its protocol.
+// These are built once per tunnel, so their size doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum AgentTunnelBuilder {
+    /// An `http` tunnel.
+    Http(HttpTunnelBuilder),
+    /// A `tcp` tunnel.
+    Tcp(TcpTunnelBuilder),
+    /// A `tls` tunnel.
+    Tls(TlsTunnelBuilder),
+    /// A tunnel with `labels` and no `proto`.
+    Labeled(LabeledTunnelBuilder),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct TunnelDef {
+    proto: Option<String>,
+    addr: Option<Value>,
+    metadata: Option<String>,
+    inspect: Option<bool>,
+    labels: Option<Vec<String>>,
+    hostname: Option<String>,
+    domain: Option<String>,
+    bind_tls: Option<Value>,
+    auth: Option<String>,
+    basic_auth: Option<Vec<String>>,
+    compression: Option<bool>,
+    websocket_tcp_converter: Option<bool>,
+    circuit_breaker: Option<f64>,
+    request_header: Option<HeaderDef>,
+    response_header: Option<HeaderDef>,
+    ip_restriction: Option<IpRestrictionDef>,
+    oauth: Option<OauthDef>,
+    oidc: Option<OidcDef>,
+    webhook_verification: Option<WebhookVerificationDef>,
+    client_cas: Option<String>,
+    crt: Option<String>,
+    key: Option<String>,
+    remote_addr: Option<String>,
+    proxy_proto: Option<Value>,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HeaderDef {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IpRestrictionDef {
+    #[serde(default)]
+    allow_cidrs: Vec<String>,
+    #[serde(default)]
+    deny_cidrs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OauthDef {
+    provider: String,
+    #[serde(default)]
+    allow_emails: Vec<String>,
+    #[serde(default)]
+    allow_domains: Vec<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OidcDef {
+    issuer_url: String,
+    client_id: String,
+    client_secret: String,
+    #[serde(default)]
+    allow_emails: Vec<String>,
+    #[serde(default)]
+    allow_domains: Vec<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebhookVerificationDef {
+    provider: String,
+    secret: String,
+}
+
+impl AgentConfig {
+    /// Load the agent config file at the given path.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AgentConfigError> {
+        Self::from_yaml(&read_to_string(path.as_ref())?)
+    }
+
+    /// Parse an agent config from its YAML contents.
+    pub fn from_yaml(contents: &str) -> Result<Self, AgentConfigError> {
+        let mut session: SessionConfig = serde_yaml::from_str(contents)?;
+        match &session.version {
+            None => {}
+            Some(Value::String(v)) if v == "2" => {}
+            Some(Value::Number(v)) if v.as_u64() == Some(2) => {}
+            Some(other) => {
+                let version = serde_yaml::to_string(other).unwrap_or_default();
+                return Err(AgentConfigError::UnsupportedVersion(
+                    version.trim().to_string(),
+                ));
+            }
+        }
+        for key in session.other.keys() {
+            if !AGENT_ONLY_KEYS.contains(&key.as_str()) {
+                return Err(AgentConfigError::UnsupportedKey {
+                    section: "agent".into(),
+                    key: key.clone(),
+                });
+            }
+            debug!(key, "ignoring agent-only config key");
+        }
+        for (key, value) in [
+            ("heartbeat_interval", &session.heartbeat_interval),
+            ("heartbeat_tolerance", &session.heartbeat_tolerance),
+        ] {
+            if let Some(value) = value {
+                parse_duration(value).map_err(|reason| AgentConfigError::InvalidValue {
+                    section: "agent".into(),
+                    key: key.into(),
+                    reason,
+                })?;
+            }
+        }
+        let tunnels = std::mem::take(&mut session.tunnels);
+        Ok(AgentConfig { session, tunnels })
+    }
+
+    /// Apply the session-level keys to the given [SessionBuilder].
+    ///
+    /// A `root_cas` of `host` trusts the operating system's root CAs in
+    /// addition to the ngrok CA, and any value other than `trusted` or `host`
+    /// is treated as the path to a PEM file of root CAs.
+    pub fn apply(&self, mut builder: SessionBuilder) -> SessionBuilder {
+        let session = &self.session;
+        if let Some(authtoken) = &session.authtoken {
+            builder = builder.authtoken(authtoken);
+        }
+        if let Some(region) = &session.region {
+            builder = builder.region(region);
+        }
+        // The server address takes precedence over the region, like it does
+        // for the agent.
+        if let Some(server_addr) = &session.server_addr {
+            builder = builder.server_addrs([server_addr]);
+        }
+        match session.root_cas.as_deref() {
+            None | Some("trusted") => {}
+            Some("host") => builder = builder.native_roots(true),
+            Some(path) => builder = builder.root_cas_file(path),
+        }
+        // Validated when the config was loaded.
+        if let Some(Ok(interval)) = session.heartbeat_interval.as_deref().map(parse_duration) {
+            builder = builder.heartbeat_interval(interval);
+        }
+        if let Some(Ok(tolerance)) = session.heartbeat_tolerance.as_deref().map(parse_duration) {
+            builder = builder.heartbeat_tolerance(tolerance);
+        }
+        if let Some(metadata) = &session.metadata {
+            builder = builder.metadata(metadata);
+        }
+        if let Some(proxy_url) = &session.proxy_url {
+            builder = builder.proxy_url(proxy_url);
+        }
+        builder
+    }
+
+    /// The names of the tunnels defined in the config, in order.
+    pub fn tunnel_names(&self) -> Vec<String> {
+        self.tunnels
+            .keys()
+            .map(|name| name.as_str().map(String::from).unwrap_or_default())
+            .collect()
+    }
+
+    /// Build each of the tunnels defined in the config, in order, to be
+    /// started in the given session.
+    ///
+    /// Files referred to by the tunnels, like `crt`, `key` and `client_cas`,
+    /// are read when this is called.
+    pub fn tunnels(&self, session: &Session) -> Result<Vec<AgentTunnel>, AgentConfigError> {
+        self.tunnels
+            .iter()
+            .map(|(name, def)| {
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| AgentConfigError::InvalidValue {
+                        section: "tunnels".into(),
+                        key: serde_yaml::to_string(name)
+                            .unwrap_or_default()
+                            .trim()
+                            .into(),
+                        reason: "tunnel names must be strings".into(),
+                    })?
+                    .to_string();
+                let def: TunnelDef = serde_yaml::from_value(def.clone())?;
+                def.build(name, session)
+            })
+            .collect()
+    }
+}
+
+impl TunnelDef {
+    // The keys that were set, so that they can be checked against those that
+    // apply to the tunnel's protocol.
+    fn set_keys(&self) -> Vec<&'static str> {
+        [
+            ("metadata", self.metadata.is_some()),
+            ("labels", self.labels.is_some()),
+            ("hostname", self.hostname.is_some()),
+            ("domain", self.domain.is_some()),
+            ("bind_tls", self.bind_tls.is_some()),
+            ("auth", self.auth.is_some()),
+            ("basic_auth", self.basic_auth.is_some()),
+            ("compression", self.compression.is_some()),
+            (
+                "websocket_tcp_converter",
+                self.websocket_tcp_converter.is_some(),
+            ),
+            ("circuit_breaker", self.circuit_breaker.is_some()),
+            ("request_header", self.request_header.is_some()),
+            ("response_header", self.response_header.is_some()),
+            ("ip_restriction", self.ip_restriction.is_some()),
+            ("oauth", self.oauth.is_some()),
+            ("oidc", self.oidc.is_some()),
+            ("webhook_verification", self.webhook_verification.is_some()),
+            ("client_cas", self.client_cas.is_some()),
+            ("crt", self.crt.is_some()),
+            ("key", self.key.is_some()),
+            ("remote_addr", self.remote_addr.is_some()),
+            ("proxy_proto", self.proxy_proto.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(key, set)| set.then_some(key))
+        .collect()
+    }
+
+    fn build(self, name: String, session: &Session) -> Result<AgentTunnel, AgentConfigError> {
+        let section = format!("tunnels.{name}");
+        let unsupported = |key: &str| AgentConfigError::UnsupportedKey {
+            section: section.clone(),
+            key: key.into(),
+        };
+        let invalid = |key: &str, reason: String| AgentConfigError::InvalidValue {
+            section: section.clone(),
+            key: key.into(),
+            reason,
+        };
+
+        if let Some(key) = self.other.keys().next() {
+            return Err(unsupported(key));
+        }
+        // Traffic inspection is a feature of the agent's web interface.
+        if self.inspect == Some(true) {
+            return Err(unsupported("inspect"));
+        }
+
+        let proto = match (self.proto.as_deref(), &self.labels) {
+            (None, Some(_)) => "labeled",
+            (Some(proto @ ("http" | "tcp" | "tls")), None) => proto,
+            (Some(_), Some(_)) => return Err(unsupported("labels")),
+            (Some(proto), None) => return Err(invalid("proto", format!("unknown proto {proto}"))),
+            (None, None) => return Err(invalid("proto", "missing proto or labels".into())),
+        };
+        let allowed: &[&str] = match proto {
+            "http" => &[
+                "metadata",
+                "hostname",
+                "domain",
+                "bind_tls",
+                "auth",
+                "basic_auth",
+                "compression",
+                "websocket_tcp_converter",
+                "circuit_breaker",
+                "request_header",
+                "response_header",
+                "ip_restriction",
+                "oauth",
+                "oidc",
+                "webhook_verification",
+                "client_cas",
+                "proxy_proto",
+            ],
+            "tcp" => &["metadata", "remote_addr", "ip_restriction", "proxy_proto"],
+            "tls" => &[
+                "metadata",
+                "hostname",
+                "domain",
+                "client_cas",
+                "crt",
+                "key",
+                "ip_restriction",
+                "proxy_proto",
+            ],
+            _ => &["metadata", "labels"],
+        };
+        if let Some(key) = self
+            .set_keys()
+            .into_iter()
+            .find(|key| !allowed.contains(key))
+        {
+            return Err(unsupported(key));
+        }
+        if self.hostname.is_some() && self.domain.is_some() {
+            return Err(invalid(
+                "hostname",
+                "only one of hostname and domain may be set".into(),
+            ));
+        }
+
+        let addr = match &self.addr {
+            None => None,
+            Some(Value::Number(port)) => Some(port.to_string()),
+            Some(Value::String(addr)) => Some(addr.clone()),
+            Some(_) => return Err(invalid("addr", "expected a port or address".into())),
+        };
+        let proxy_proto = match &self.proxy_proto {
+            None => ProxyProto::None,
+            Some(value) => match value
+                .as_i64()
+                .or_else(|| value.as_str().and_then(|v| v.parse().ok()))
+            {
+                Some(version @ 0..=2) => ProxyProto::from(version),
+                _ => return Err(invalid("proxy_proto", "expected 1 or 2".into())),
+            },
+        };
+        let domain = self.hostname.clone().or_else(|| self.domain.clone());
+        let (allow_cidrs, deny_cidrs) = self
+            .ip_restriction
+            .clone()
+            .map(|r| (r.allow_cidrs, r.deny_cidrs))
+            .unwrap_or_default();
+        let client_cas = self.client_cas.as_deref().map(read_file).transpose()?;
+        let forwards_to = addr.clone();
+
+        // Apply the options shared by the http, tcp and tls builders.
+        macro_rules! common {
+            ($builder:expr) => {{
+                let mut builder = $builder.proxy_proto(proxy_proto);
+                if let Some(metadata) = &self.metadata {
+                    builder = builder.metadata(metadata);
+                }
+                if let Some(forwards_to) = &forwards_to {
+                    builder = builder.forwards_to(forwards_to);
+                }
+                for cidr in &allow_cidrs {
+                    builder = builder.allow_cidr_string(cidr);
+                }
+                for cidr in &deny_cidrs {
+                    builder = builder.deny_cidr_string(cidr);
+                }
+                builder
+            }};
+        }
+
+        let builder = match proto {
+            "http" => {
+                let mut builder = common!(session.http_endpoint());
+                match &self.bind_tls {
+                    None => {}
+                    Some(Value::Bool(true)) => builder = builder.scheme(Scheme::HTTPS),
+                    Some(Value::Bool(false)) => builder = builder.scheme(Scheme::HTTP),
+                    Some(_) => {
+                        return Err(invalid(
+                            "bind_tls",
+                            "only true or false are supported".into(),
+                        ))
+                    }
+                }
+                if let Some(domain) = domain {
+                    builder = builder.domain(domain);
+                }
+                if let Some(ca) = client_cas {
+                    builder = builder.mutual_tlsca(ca);
+                }
+                for creds in self.auth.iter().chain(self.basic_auth.iter().flatten()) {
+                    let (username, password) = creds.split_once(':').ok_or_else(|| {
+                        invalid("basic_auth", "expected username:password".into())
+                    })?;
+                    builder = builder.basic_auth(username, password);
+                }
+                if self.compression == Some(true) {
+                    builder = builder.compression();
+                }
+                if self.websocket_tcp_converter == Some(true) {
+                    builder = builder.websocket_tcp_conversion();
+                }
+                if let Some(circuit_breaker) = self.circuit_breaker {
+                    builder = builder.circuit_breaker(circuit_breaker);
+                }
+                if let Some(headers) = &self.request_header {
+                    for header in &headers.add {
+                        let (name, value) = split_header(header)
+                            .ok_or_else(|| invalid("request_header", header_reason(header)))?;
+                        builder = builder.request_header(name, value);
+                    }
+                    for name in &headers.remove {
+                        builder = builder.remove_request_header(name);
+                    }
+                }
+                if let Some(headers) = &self.response_header {
+                    for header in &headers.add {
+                        let (name, value) = split_header(header)
+                            .ok_or_else(|| invalid("response_header", header_reason(header)))?;
+                        builder = builder.response_header(name, value);
+                    }
+                    for name in &headers.remove {
+                        builder = builder.remove_response_header(name);
+                    }
+                }
+                if let Some(oauth) = self.oauth {
+                    let mut opts = OauthOptions::new(oauth.provider);
+                    for email in oauth.allow_emails {
+                        opts = opts.allow_email(email);
+                    }
+                    for domain in oauth.allow_domains {
+                        opts = opts.allow_domain(domain);
+                    }
+                    for scope in oauth.scopes {
+                        opts = opts.scope(scope);
+                    }
+                    builder = builder.oauth(opts);
+                }
+                if let Some(oidc) = self.oidc {
+                    let mut opts =
+                        OidcOptions::new(oidc.issuer_url, oidc.client_id, oidc.client_secret);
+                    for email in oidc.allow_emails {
+                        opts = opts.allow_email(email);
+                    }
+                    for domain in oidc.allow_domains {
+                        opts = opts.allow_domain(domain);
+                    }
+                    for scope in oidc.scopes {
+                        opts = opts.scope(scope);
+                    }
+                    builder = builder.oidc(opts);
+                }
+                if let Some(webhook) = self.webhook_verification {
+                    builder = builder.webhook_verification(webhook.provider, webhook.secret);
+                }
+                AgentTunnelBuilder::Http(builder)
+            }
+            "tcp" => {
+                let mut builder = common!(session.tcp_endpoint());
+                if let Some(remote_addr) = &self.remote_addr {
+                    builder = builder.remote_addr(remote_addr);
+                }
+                AgentTunnelBuilder::Tcp(builder)
+            }
+            "tls" => {
+                let mut builder = common!(session.tls_endpoint());
+                if let Some(domain) = domain {
+                    builder = builder.domain(domain);
+                }
+                if let Some(ca) = client_cas {
+                    builder = builder.mutual_tlsca(ca);
+                }
+                match (&self.crt, &self.key) {
+                    (Some(crt), Some(key)) => {
+                        builder = builder.cert_pem(read_file(crt)?).key_pem(read_file(key)?);
+                    }
+                    (None, None) => {}
+                    (Some(_), None) => return Err(invalid("key", "required with crt".into())),
+                    (None, Some(_)) => return Err(invalid("crt", "required with key".into())),
+                }
+                AgentTunnelBuilder::Tls(builder)
+            }
+            _ => {
+                let mut builder = session.labeled_tunnel();
+                if let Some(metadata) = &self.metadata {
+                    builder = builder.metadata(metadata);
+                }
+                for label in self.labels.iter().flatten() {
+                    let (key, value) = label
+                        .split_once('=')
+                        .ok_or_else(|| invalid("labels", format!("expected key=value: {label}")))?;
+                    builder = builder.label(key, value);
+                }
+                AgentTunnelBuilder::Labeled(builder)
+            }
+        };
+
+        Ok(AgentTunnel {
+            name,
+            addr,
+            builder,
+        })
+    }
+}
+
+fn split_header(header: &str) -> Option<(&str, &str)> {
+    header
+        .split_once(':')
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+fn header_reason(header: &str) -> String {
+    format!("expected name: value: {header}")
+}
+
+fn read_to_string(path: &Path) -> Result<String, AgentConfigError> {
+    std::fs::read_to_string(path).map_err(|error| AgentConfigError::Read {
+        path: path.display().to_string(),
+        error,
+    })
+}
+
+fn read_file(path: &str) -> Result<Bytes, AgentConfigError> {
+    std::fs::read(path)
+        .map(Bytes::from)
+        .map_err(|error| AgentConfigError::Read {
+            path: path.into(),
+            error,
+        })
+}
+
+// Parse a duration in the format used by Go, e.g. `1m30s` or `500ms`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration: {s}");
+    if s == "0" {
+        return Ok(Duration::ZERO);
+    }
+    let mut rest = s;
+    let mut total = Duration::ZERO;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(number_len);
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let scale = match unit {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        total = Duration::try_from_secs_f64(number * scale)
+            .ok()
+            .and_then(|duration| total.checked_add(duration))
+            .ok_or_else(invalid)?;
+        rest = tail;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(Duration::from_secs(10)), parse_duration("10s"));
+        assert_eq!(Ok(Duration::from_secs(90)), parse_duration("1m30s"));
+        assert_eq!(Ok(Duration::from_millis(1500)), parse_duration("1.5s"));
+        assert_eq!(Ok(Duration::from_millis(250)), parse_duration("250ms"));
+        assert_eq!(Ok(Duration::ZERO), parse_duration("0"));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+        assert!(parse_duration("3000000000000000h3000000000000000h").is_err());
+    }
+
+    #[test]
+    fn test_session_keys() {
+        let config = AgentConfig::from_yaml(
+            "version: \"2\"\n\
+             authtoken: abc\n\
+             server_addr: tunnel.example.com:443\n\
+             heartbeat_interval: 10s\n\
+             web_addr: localhost:4040\n",
+        )
+        .unwrap();
+        assert_eq!(Some("abc"), config.session.authtoken.as_deref());
+        assert!(config.tunnel_names().is_empty());
+
+        assert!(matches!(
+            AgentConfig::from_yaml("version: 3\n"),
+            Err(AgentConfigError::UnsupportedVersion(v)) if v == "3"
+        ));
+        assert!(matches!(
+            AgentConfig::from_yaml("version: \"2\"\nbogus: true\n"),
+            Err(AgentConfigError::UnsupportedKey { key, .. }) if key == "bogus"
+        ));
+        assert!(matches!(
+            AgentConfig::from_yaml("heartbeat_interval: soon\n"),
+            Err(AgentConfigError::InvalidValue { key, .. }) if key == "heartbeat_interval"
+        ));
+        assert!(matches!(
+            AgentConfig::from_yaml("heartbeat_interval: 99999999999999999999h\n"),
+            Err(AgentConfigError::InvalidValue { key, .. }) if key == "heartbeat_interval"
+        ));
+    }
+}
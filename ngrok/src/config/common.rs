@@ -1,6 +1,15 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
 
+use async_rustls::rustls::ServerConfig;
 use async_trait::async_trait;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 pub use crate::internals::proto::ProxyProto;
 use crate::{
@@ -46,6 +55,29 @@ macro_rules! impl_builder {
             }
         }
 
+        impl $name {
+            /// Create a builder for a tunnel with the given options, to be
+            /// started in the given session.
+            pub fn from_options(session: Session, options: $opts) -> Self {
+                $name {
+                    options,
+                    session: session.into(),
+                }
+            }
+
+            /// The options that the tunnel will be started with, e.g. to
+            /// serialize them.
+            pub fn options(&self) -> &$opts {
+                &self.options
+            }
+
+            /// Use this configuration to start a tunnel in a different session.
+            pub(crate) fn for_session(mut self, session: Session) -> Self {
+                self.session = Some(session);
+                self
+            }
+        }
+
         #[async_trait]
         impl TunnelBuilder for $name {
             type Tunnel = $tun;
@@ -79,6 +111,17 @@ pub(crate) trait TunnelConfig {
     fn opts(&self) -> Option<BindOpts>;
     /// The labels for this tunnel.
     fn labels(&self) -> HashMap<String, String>;
+    /// The timeout for binding this tunnel, if it overrides the session's.
+    fn listen_timeout(&self) -> Option<Duration>;
+    /// Whether to strip and parse the PROXY protocol header from incoming
+    /// connections.
+    fn decode_proxy_proto(&self) -> bool;
+    /// The config to terminate TLS with for incoming connections, and the
+    /// deadline for the handshake, if it's terminated by the agent rather
+    /// than the edge.
+    fn termination_at_agent(&self) -> Option<(Arc<ServerConfig>, Duration)> {
+        None
+    }
 }
 
 // delegate references
@@ -101,14 +144,26 @@ where
     fn labels(&self) -> HashMap<String, String> {
         (**self).labels()
     }
+    fn listen_timeout(&self) -> Option<Duration> {
+        (**self).listen_timeout()
+    }
+    fn decode_proxy_proto(&self) -> bool {
+        (**self).decode_proxy_proto()
+    }
+    fn termination_at_agent(&self) -> Option<(Arc<ServerConfig>, Duration)> {
+        (**self).termination_at_agent()
+    }
 }
 
 /// Restrictions placed on the origin of incoming connections to the edge.
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct CidrRestrictions {
     /// Rejects connections that do not match the given CIDRs
+    #[serde(rename = "allow_cidrs", skip_serializing_if = "Vec::is_empty")]
     pub(crate) allowed: Vec<String>,
     /// Rejects connections that match the given CIDRs and allows all other CIDRs.
+    #[serde(rename = "deny_cidrs", skip_serializing_if = "Vec::is_empty")]
     pub(crate) denied: Vec<String>,
 }
 
@@ -122,21 +177,36 @@ impl CidrRestrictions {
 }
 
 // Common
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct CommonOpts {
     // Restrictions placed on the origin of incoming connections to the edge.
+    #[serde(flatten)]
     pub(crate) cidr_restrictions: CidrRestrictions,
     // The version of PROXY protocol to use with this tunnel, zero if not
     // using.
     pub(crate) proxy_proto: ProxyProto,
     // Tunnel-specific opaque metadata. Viewable via the API.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metadata: Option<String>,
     // Tunnel backend metadata. Viewable via the dashboard and API, but has no
     // bearing on tunnel behavior.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) forwards_to: Option<String>,
+    // Overrides the session's RPC timeout when binding the tunnel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) listen_timeout: Option<Duration>,
+    // Strip and parse the PROXY protocol header from incoming connections.
+    pub(crate) decode_proxy_proto: bool,
 }
 
 impl CommonOpts {
+    // Whether to decode the PROXY header from incoming connections to an
+    // endpoint. There won't be one unless the edge is told to send it.
+    pub(crate) fn decodes_edge_proxy_proto(&self) -> bool {
+        self.decode_proxy_proto && self.proxy_proto != ProxyProto::None
+    }
+
     // Get the proto version of cidr restrictions
     pub(crate) fn ip_restriction(&self) -> Option<IpRestriction> {
         (!self.cidr_restrictions.allowed.is_empty() || !self.cidr_restrictions.denied.is_empty())
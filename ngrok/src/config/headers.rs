@@ -1,13 +1,21 @@
 use std::collections::HashMap;
 
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
 use crate::internals::proto::Headers as HeaderProto;
 
 /// HTTP Headers to modify at the ngrok edge.
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub(crate) struct Headers {
     /// Headers to add to requests or responses at the ngrok edge.
+    #[serde(rename = "add", skip_serializing_if = "HashMap::is_empty")]
     added: HashMap<String, String>,
     /// Header names to remove from requests or responses at the ngrok edge.
+    #[serde(rename = "remove", skip_serializing_if = "Vec::is_empty")]
     removed: Vec<String>,
 }
 
@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use bytes::{
     self,
     Bytes,
 };
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use super::{
     common::ProxyProto,
@@ -20,6 +27,10 @@ use crate::{
         headers::Headers,
         oauth::OauthOptions,
         oidc::OidcOptions,
+        redact::{
+            pem_list,
+            secret,
+        },
         webhook_verification::WebhookVerification,
     },
     internals::proto::{
@@ -30,6 +41,7 @@ use crate::{
         CircuitBreaker,
         Compression,
         HttpEndpoint,
+        SecretString,
         WebsocketTcpConverter,
     },
     session::RpcError,
@@ -40,7 +52,8 @@ use crate::{
 /// The URL scheme for this HTTP endpoint.
 ///
 /// [Scheme::HTTPS] will enable TLS termination at the ngrok edge.
-#[derive(Clone, Default, Eq, PartialEq)]
+#[derive(Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Scheme {
     /// The `http` URL scheme.
     HTTP,
@@ -50,23 +63,45 @@ pub enum Scheme {
 }
 
 /// The options for a HTTP edge.
-#[derive(Default, Clone)]
-struct HttpOptions {
+///
+/// Built with an [HttpTunnelBuilder], and serializable so that they can be
+/// stored and used to start the tunnel later with
+/// [HttpTunnelBuilder::from_options]. Basic auth passwords and other secrets
+/// are redacted when serialized, unless serialized
+/// [WithSecrets](crate::config::WithSecrets).
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpOptions {
+    #[serde(flatten)]
     pub(crate) common_opts: CommonOpts,
     pub(crate) scheme: Scheme,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) domain: Option<String>,
+    #[serde(with = "pem_list", skip_serializing_if = "Vec::is_empty")]
     pub(crate) mutual_tlsca: Vec<bytes::Bytes>,
     pub(crate) compression: bool,
     pub(crate) websocket_tcp_conversion: bool,
     pub(crate) circuit_breaker: f64,
     pub(crate) request_headers: Headers,
     pub(crate) response_headers: Headers,
-    pub(crate) basic_auth: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) basic_auth: Vec<BasicAuthOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) oauth: Option<OauthOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) oidc: Option<OidcOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) webhook_verification: Option<WebhookVerification>,
 }
 
+/// Credentials for basic authentication.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct BasicAuthOptions {
+    pub(crate) username: String,
+    #[serde(with = "secret")]
+    pub(crate) password: SecretString,
+}
+
 impl TunnelConfig for HttpOptions {
     fn forwards_to(&self) -> String {
         self.common_opts
@@ -121,11 +156,17 @@ impl TunnelConfig for HttpOptions {
     fn labels(&self) -> HashMap<String, String> {
         HashMap::new()
     }
+    fn listen_timeout(&self) -> Option<Duration> {
+        self.common_opts.listen_timeout
+    }
+    fn decode_proxy_proto(&self) -> bool {
+        self.common_opts.decodes_edge_proxy_proto()
+    }
 }
 
 // transform into the wire protocol format
-impl From<&[(String, String)]> for BasicAuth {
-    fn from(v: &[(String, String)]) -> Self {
+impl From<&[BasicAuthOptions]> for BasicAuth {
+    fn from(v: &[BasicAuthOptions]) -> Self {
         BasicAuth {
             credentials: v.iter().cloned().map(From::from).collect(),
         }
@@ -133,11 +174,11 @@ impl From<&[(String, String)]> for BasicAuth {
 }
 
 // transform into the wire protocol format
-impl From<(String, String)> for BasicAuthCredential {
-    fn from(b: (String, String)) -> Self {
+impl From<BasicAuthOptions> for BasicAuthCredential {
+    fn from(b: BasicAuthOptions) -> Self {
         BasicAuthCredential {
-            username: b.0,
-            cleartext_password: b.1,
+            username: b.username,
+            cleartext_password: String::clone(&b.password),
             hashed_password: vec![], // unused in this context
         }
     }
@@ -166,6 +207,18 @@ impl HttpTunnelBuilder {
         self.options.common_opts.proxy_proto = proxy_proto;
         self
     }
+    /// Use this version of PROXY protocol, and strip and parse the header
+    /// rather than passing it through. See [Conn::proxy_header](crate::Conn::proxy_header).
+    pub fn decode_proxy_proto(mut self, version: ProxyProto) -> Self {
+        self.options.common_opts.proxy_proto = version;
+        self.options.common_opts.decode_proxy_proto = version != ProxyProto::None;
+        self
+    }
+    /// Override the session's RPC timeout when starting this tunnel.
+    pub fn listen_timeout(mut self, timeout: Duration) -> Self {
+        self.options.common_opts.listen_timeout = Some(timeout);
+        self
+    }
     /// Tunnel-specific opaque metadata. Viewable via the API.
     pub fn metadata(mut self, metadata: impl Into<String>) -> Self {
         self.options.common_opts.metadata = Some(metadata.into());
@@ -234,9 +287,10 @@ impl HttpTunnelBuilder {
     /// Credentials for basic authentication.
     /// If not called, basic authentication is disabled.
     pub fn basic_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
-        self.options
-            .basic_auth
-            .push((username.into(), password.into()));
+        self.options.basic_auth.push(BasicAuthOptions {
+            username: username.into(),
+            password: password.into().into(),
+        });
         self
     }
 
@@ -272,6 +326,7 @@ impl HttpTunnelBuilder {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::config::WithSecrets;
 
     const METADATA: &str = "testmeta";
     const TEST_FORWARD: &str = "testforward";
@@ -326,6 +381,62 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_serde() {
+        let options = HttpTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        }
+        .allow_cidr_string(ALLOW_CIDR)
+        .deny_cidr_string(DENY_CIDR)
+        .proxy_proto(ProxyProto::V2)
+        .metadata(METADATA)
+        .scheme(Scheme::HTTPS)
+        .domain(DOMAIN)
+        .mutual_tlsca(CA_CERT.into())
+        .mutual_tlsca(CA_CERT2.into())
+        .compression()
+        .websocket_tcp_conversion()
+        .circuit_breaker(0.5)
+        .request_header("X-Req-Yup", "true")
+        .response_header("X-Res-Yup", "true")
+        .remove_request_header("X-Req-Nope")
+        .remove_response_header("X-Res-Nope")
+        .oauth(OauthOptions::new("google"))
+        .oauth(
+            OauthOptions::new("google")
+                .allow_email("<user>@<domain>")
+                .allow_domain("<domain>")
+                .scope("<scope>"),
+        )
+        .oidc(OidcOptions::new("<url>", "<id>", "<secret>"))
+        .oidc(
+            OidcOptions::new("<url>", "<id>", "<secret>")
+                .allow_email("<user>@<domain>")
+                .allow_domain("<domain>")
+                .scope("<scope>"),
+        )
+        .webhook_verification("twilio", "asdf")
+        .basic_auth("ngrok", "online1line")
+        .forwards_to(TEST_FORWARD)
+        .options;
+
+        let json = serde_json::to_string(&WithSecrets(&options)).unwrap();
+        let restored: HttpOptions = serde_json::from_str(&json).unwrap();
+        tunnel_test(restored);
+
+        let redacted = serde_json::to_string(&options).unwrap();
+        assert!(!redacted.contains("online1line"));
+        assert!(!redacted.contains("<secret>"));
+        assert!(!redacted.contains("asdf"));
+        assert!(redacted.contains("\"allow_cidrs\":[\"0.0.0.0/0\"]"));
+        assert!(serde_json::from_str::<HttpOptions>(&redacted).is_err());
+
+        let defaults: HttpOptions = serde_json::from_str("{\"domain\":\"a.ngrok.io\"}").unwrap();
+        assert_eq!(Some("a.ngrok.io"), defaults.domain.as_deref());
+        assert_eq!("https", defaults.proto());
+    }
+
     fn tunnel_test<C>(tunnel_cfg: C)
     where
         C: TunnelConfig,
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use super::TunnelBuilder;
 use crate::{
@@ -19,8 +26,14 @@ use crate::{
 };
 
 /// Options for labeled tunnels.
-#[derive(Default, Clone)]
-struct LabeledOptions {
+///
+/// Built with a [LabeledTunnelBuilder], and serializable so that they can be
+/// stored and used to start the tunnel later with
+/// [LabeledTunnelBuilder::from_options].
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LabeledOptions {
+    #[serde(flatten)]
     pub(crate) common_opts: CommonOpts,
     pub(crate) labels: HashMap<String, String>,
 }
@@ -48,6 +61,12 @@ impl TunnelConfig for LabeledOptions {
     fn labels(&self) -> HashMap<String, String> {
         self.labels.clone()
     }
+    fn listen_timeout(&self) -> Option<Duration> {
+        self.common_opts.listen_timeout
+    }
+    fn decode_proxy_proto(&self) -> bool {
+        self.common_opts.decode_proxy_proto
+    }
 }
 
 impl_builder! {
@@ -56,6 +75,18 @@ impl_builder! {
 }
 
 impl LabeledTunnelBuilder {
+    /// Strip and parse the PROXY protocol header that the edge is configured
+    /// to send. See [Conn::proxy_header](crate::Conn::proxy_header).
+    pub fn decode_proxy_proto(mut self) -> Self {
+        self.options.common_opts.decode_proxy_proto = true;
+        self
+    }
+    /// Override the session's RPC timeout when starting this tunnel.
+    pub fn listen_timeout(mut self, timeout: Duration) -> Self {
+        self.options.common_opts.listen_timeout = Some(timeout);
+        self
+    }
+
     /// Tunnel-specific opaque metadata. Viewable via the API.
     pub fn metadata(mut self, metadata: impl Into<String>) -> Self {
         self.options.common_opts.metadata = Some(metadata.into());
@@ -92,6 +123,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_serde() {
+        let options = LabeledTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        }
+        .metadata(METADATA)
+        .label(LABEL_KEY, LABEL_VAL)
+        .options;
+
+        let json = serde_json::to_value(&options).unwrap();
+        assert_eq!(LABEL_VAL, json["labels"][LABEL_KEY]);
+        tunnel_test(serde_json::from_value::<LabeledOptions>(json).unwrap());
+
+        let defaults: LabeledOptions = serde_json::from_str("{}").unwrap();
+        assert!(defaults.labels.is_empty());
+    }
+
     fn tunnel_test<C>(tunnel_cfg: C)
     where
         C: TunnelConfig,
@@ -1,15 +1,23 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
 use crate::internals::proto::Oauth;
 
 /// Oauth Options configuration
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct OauthOptions {
     /// The OAuth provider to use
     provider: String,
     /// Email addresses of users to authorize.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     allow_emails: Vec<String>,
     /// Email domains of users to authorize.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     allow_domains: Vec<String>,
     /// OAuth scopes to request from the provider.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     scopes: Vec<String>,
 }
 
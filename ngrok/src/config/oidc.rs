@@ -1,16 +1,31 @@
-use crate::internals::proto::{
-    Oidc,
-    SecretString,
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    config::redact::secret,
+    internals::proto::{
+        Oidc,
+        SecretString,
+    },
 };
 
 /// Oidc Options configuration
-#[derive(Clone, Default)]
+///
+/// The client secret is redacted when serialized, unless serialized
+/// [WithSecrets](crate::config::WithSecrets).
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct OidcOptions {
     issuer_url: String,
     client_id: String,
+    #[serde(with = "secret")]
     client_secret: SecretString,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     allow_emails: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     allow_domains: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     scopes: Vec<String>,
 }
 
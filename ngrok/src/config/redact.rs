@@ -0,0 +1,176 @@
+use std::{
+    cell::Cell,
+    ops::Deref,
+};
+
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+
+// What secrets are replaced with when they're redacted.
+const REDACTED: &str = "********";
+
+thread_local! {
+    // Whether secrets are being included in the current serialization.
+    static INCLUDE_SECRETS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Serializes the wrapped tunnel options with their secrets included.
+///
+/// By default, secrets such as basic auth passwords, OIDC client secrets,
+/// webhook verification secrets and TLS private keys are replaced with
+/// `********` when tunnel options are serialized, and can't be deserialized
+/// again. Serializing `WithSecrets(&options)` instead keeps them.
+#[derive(Debug, Clone, Copy)]
+pub struct WithSecrets<T>(pub T);
+
+impl<T> Serialize for WithSecrets<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Restores the previous setting, even if serialization panics.
+        struct Reset(bool);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                INCLUDE_SECRETS.with(|include| include.set(self.0));
+            }
+        }
+        let _reset = Reset(INCLUDE_SECRETS.with(|include| include.replace(true)));
+        self.0.serialize(serializer)
+    }
+}
+
+fn include_secrets() -> bool {
+    INCLUDE_SECRETS.with(Cell::get)
+}
+
+fn check_redacted<E: serde::de::Error>(value: &str) -> Result<(), E> {
+    if value == REDACTED {
+        return Err(E::custom(
+            "secret was redacted when serialized, serialize with WithSecrets to keep it",
+        ));
+    }
+    Ok(())
+}
+
+fn pem_string<E: serde::ser::Error>(pem: &[u8]) -> Result<&str, E> {
+    std::str::from_utf8(pem).map_err(|_| E::custom("expected PEM-encoded data"))
+}
+
+/// (De)serialization for secret strings, which are redacted unless serialized
+/// [WithSecrets].
+pub(crate) mod secret {
+    use super::*;
+
+    pub(crate) fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Deref<Target = String>,
+        S: Serializer,
+    {
+        if include_secrets() {
+            serializer.serialize_str(value)
+        } else {
+            serializer.serialize_str(REDACTED)
+        }
+    }
+
+    pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<String>,
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        check_redacted(&value)?;
+        Ok(value.into())
+    }
+}
+
+/// (De)serialization for PEM-encoded data as strings.
+pub(crate) mod pem {
+    use bytes::Bytes;
+
+    use super::*;
+
+    pub(crate) fn serialize<S>(value: &Option<Bytes>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value
+            .as_deref()
+            .map(pem_string::<S::Error>)
+            .transpose()?
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Bytes>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<String>::deserialize(deserializer)?.map(Bytes::from))
+    }
+}
+
+/// (De)serialization for lists of PEM-encoded data as strings.
+pub(crate) mod pem_list {
+    use bytes::Bytes;
+
+    use super::*;
+
+    pub(crate) fn serialize<S>(value: &[Bytes], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value
+            .iter()
+            .map(|pem| pem_string::<S::Error>(pem))
+            .collect::<Result<Vec<_>, _>>()?
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Bytes>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(Bytes::from)
+            .collect())
+    }
+}
+
+/// (De)serialization for secret PEM-encoded data, like private keys, which
+/// is redacted unless serialized [WithSecrets].
+pub(crate) mod secret_pem {
+    use bytes::Bytes;
+
+    use super::*;
+
+    pub(crate) fn serialize<S>(value: &Option<Bytes>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match value {
+            Some(pem) if include_secrets() => serializer.serialize_some(pem_string(pem)?),
+            Some(_) => serializer.serialize_some(REDACTED),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Bytes>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Option::<String>::deserialize(deserializer)?;
+        if let Some(pem) = &value {
+            check_redacted::<D::Error>(pem)?;
+        }
+        Ok(value.map(Bytes::from))
+    }
+}
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use super::{
     common::ProxyProto,
@@ -23,9 +30,16 @@ use crate::{
 };
 
 /// The options for a TCP edge.
-#[derive(Default, Clone)]
-struct TcpOptions {
+///
+/// Built with a [TcpTunnelBuilder], and serializable so that they can be
+/// stored and used to start the tunnel later with
+/// [TcpTunnelBuilder::from_options].
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpOptions {
+    #[serde(flatten)]
     pub(crate) common_opts: CommonOpts,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) remote_addr: Option<String>,
 }
 
@@ -62,6 +76,12 @@ impl TunnelConfig for TcpOptions {
     fn labels(&self) -> HashMap<String, String> {
         HashMap::new()
     }
+    fn listen_timeout(&self) -> Option<Duration> {
+        self.common_opts.listen_timeout
+    }
+    fn decode_proxy_proto(&self) -> bool {
+        self.common_opts.decodes_edge_proxy_proto()
+    }
 }
 
 impl_builder! {
@@ -88,6 +108,18 @@ impl TcpTunnelBuilder {
         self.options.common_opts.proxy_proto = proxy_proto;
         self
     }
+    /// Use this version of PROXY protocol, and strip and parse the header
+    /// rather than passing it through. See [Conn::proxy_header](crate::Conn::proxy_header).
+    pub fn decode_proxy_proto(mut self, version: ProxyProto) -> Self {
+        self.options.common_opts.proxy_proto = version;
+        self.options.common_opts.decode_proxy_proto = version != ProxyProto::None;
+        self
+    }
+    /// Override the session's RPC timeout when starting this tunnel.
+    pub fn listen_timeout(mut self, timeout: Duration) -> Self {
+        self.options.common_opts.listen_timeout = Some(timeout);
+        self
+    }
     /// Tunnel-specific opaque metadata. Viewable via the API.
     pub fn metadata(mut self, metadata: impl Into<String>) -> Self {
         self.options.common_opts.metadata = Some(metadata.into());
@@ -135,6 +167,50 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_serde() {
+        let options = TcpTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        }
+        .allow_cidr_string(ALLOW_CIDR)
+        .deny_cidr_string(DENY_CIDR)
+        .proxy_proto(ProxyProto::V2)
+        .metadata(METADATA)
+        .remote_addr(REMOTE_ADDR)
+        .forwards_to(TEST_FORWARD)
+        .options;
+
+        let json = serde_json::to_value(&options).unwrap();
+        assert_eq!(REMOTE_ADDR, json["remote_addr"]);
+        tunnel_test(serde_json::from_value::<TcpOptions>(json).unwrap());
+
+        let defaults: TcpOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(None, defaults.remote_addr);
+    }
+
+    #[test]
+    fn test_decode_proxy_proto() {
+        let builder = TcpTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        };
+        let options = builder.clone().decode_proxy_proto(ProxyProto::V1).options;
+        assert!(options.decode_proxy_proto());
+        assert!(matches!(
+            options.opts(),
+            Some(BindOpts::Tcp(endpoint)) if endpoint.proxy_proto == ProxyProto::V1
+        ));
+
+        let options = builder.decode_proxy_proto(ProxyProto::None).options;
+        assert!(!options.decode_proxy_proto());
+
+        // There's nothing to decode if the edge isn't sending headers.
+        let options: TcpOptions =
+            serde_json::from_str("{\"decode_proxy_proto\":true,\"proxy_proto\":0}").unwrap();
+        assert!(!options.decode_proxy_proto());
+    }
+
     fn tunnel_test<C>(tunnel_cfg: C)
     where
         C: TunnelConfig,
@@ -1,20 +1,36 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
 
+use async_rustls::rustls::ServerConfig;
 use async_trait::async_trait;
 use bytes::{
     self,
     Bytes,
 };
+use serde::{
+    Deserialize,
+    Serialize,
+};
 
 use super::{
     common::ProxyProto,
     TunnelBuilder,
 };
 use crate::{
-    config::common::{
-        CommonOpts,
-        TunnelConfig,
-        FORWARDS_TO,
+    config::{
+        common::{
+            CommonOpts,
+            TunnelConfig,
+            FORWARDS_TO,
+        },
+        redact::{
+            pem,
+            pem_list,
+            secret_pem,
+        },
     },
     internals::proto::{
         self,
@@ -27,14 +43,36 @@ use crate::{
     Session,
 };
 
+// How long the client gets to complete the TLS handshake when it's terminated
+// by the agent, unless overridden by [TlsTunnelBuilder::handshake_timeout].
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// The options for TLS edges.
-#[derive(Default, Clone)]
-struct TlsOptions {
+///
+/// Built with a [TlsTunnelBuilder], and serializable so that they can be
+/// stored and used to start the tunnel later with
+/// [TlsTunnelBuilder::from_options]. The private key is redacted when
+/// serialized, unless serialized [WithSecrets](crate::config::WithSecrets).
+/// The server config for [TlsTunnelBuilder::termination_at_agent] isn't
+/// serialized.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsOptions {
+    #[serde(flatten)]
     pub(crate) common_opts: CommonOpts,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) domain: Option<String>,
+    #[serde(with = "pem_list", skip_serializing_if = "Vec::is_empty")]
     pub(crate) mutual_tlsca: Vec<bytes::Bytes>,
+    #[serde(with = "secret_pem", skip_serializing_if = "Option::is_none")]
     pub(crate) key_pem: Option<bytes::Bytes>,
+    #[serde(with = "pem", skip_serializing_if = "Option::is_none")]
     pub(crate) cert_pem: Option<bytes::Bytes>,
+    #[serde(skip)]
+    pub(crate) termination_at_agent: Option<Arc<ServerConfig>>,
+    pub(crate) mutual_tls_at_agent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) handshake_timeout: Option<Duration>,
 }
 
 impl TunnelConfig for TlsOptions {
@@ -69,11 +107,14 @@ impl TunnelConfig for TlsOptions {
             .cert_pem
             .as_ref()
             .zip(self.key_pem.as_ref())
+            .filter(|_| self.termination_at_agent.is_none())
             .map(|(c, k)| TlsTermination {
                 cert: c.to_vec(),
                 key: k.to_vec().into(),
                 sealed_key: Vec::new(),
             });
+        tls_endpoint.mutual_tls_at_agent =
+            self.termination_at_agent.is_some() && self.mutual_tls_at_agent;
 
         tls_endpoint.ip_restriction = self.common_opts.ip_restriction();
         tls_endpoint.mutual_tls_at_edge =
@@ -85,6 +126,18 @@ impl TunnelConfig for TlsOptions {
     fn labels(&self) -> HashMap<String, String> {
         HashMap::new()
     }
+    fn listen_timeout(&self) -> Option<Duration> {
+        self.common_opts.listen_timeout
+    }
+    fn decode_proxy_proto(&self) -> bool {
+        self.common_opts.decodes_edge_proxy_proto()
+    }
+    fn termination_at_agent(&self) -> Option<(Arc<ServerConfig>, Duration)> {
+        let handshake_timeout = self.handshake_timeout.unwrap_or(DEFAULT_HANDSHAKE_TIMEOUT);
+        self.termination_at_agent
+            .clone()
+            .map(|config| (config, handshake_timeout))
+    }
 }
 
 impl_builder! {
@@ -110,6 +163,18 @@ impl TlsTunnelBuilder {
         self.options.common_opts.proxy_proto = proxy_proto;
         self
     }
+    /// Use this version of PROXY protocol, and strip and parse the header
+    /// rather than passing it through. See [Conn::proxy_header](crate::Conn::proxy_header).
+    pub fn decode_proxy_proto(mut self, version: ProxyProto) -> Self {
+        self.options.common_opts.proxy_proto = version;
+        self.options.common_opts.decode_proxy_proto = version != ProxyProto::None;
+        self
+    }
+    /// Override the session's RPC timeout when starting this tunnel.
+    pub fn listen_timeout(mut self, timeout: Duration) -> Self {
+        self.options.common_opts.listen_timeout = Some(timeout);
+        self
+    }
     /// Tunnel-specific opaque metadata. Viewable via the API.
     pub fn metadata(mut self, metadata: impl Into<String>) -> Self {
         self.options.common_opts.metadata = Some(metadata.into());
@@ -142,11 +207,43 @@ impl TlsTunnelBuilder {
         self.options.cert_pem = Some(cert_pem);
         self
     }
+    /// Terminate TLS in this process with the given server config, rather than
+    /// at the ngrok edge, so that the edge never sees the plaintext.
+    ///
+    /// Connections yield the decrypted stream, and the negotiated SNI, ALPN,
+    /// and client certificate chain are available from
+    /// [Conn::tls_info](crate::Conn::tls_info). Configure client
+    /// authentication on the server config to require mutual TLS, and enable
+    /// [TlsTunnelBuilder::mutual_tls_at_agent]. This overrides
+    /// [TlsTunnelBuilder::cert_pem] and [TlsTunnelBuilder::key_pem].
+    pub fn termination_at_agent(mut self, config: ServerConfig) -> Self {
+        self.options.termination_at_agent = Some(Arc::new(config));
+        self
+    }
+    /// Tell the edge that clients authenticate with certificates at the agent,
+    /// for a [TlsTunnelBuilder::termination_at_agent] config with client auth.
+    pub fn mutual_tls_at_agent(mut self) -> Self {
+        self.options.mutual_tls_at_agent = true;
+        self
+    }
+    /// Set how long clients have to complete the TLS handshake when it's
+    /// terminated by [TlsTunnelBuilder::termination_at_agent]. Connections
+    /// that take longer end with a per-connection
+    /// [AcceptError::TlsHandshake](crate::tunnel::AcceptError::TlsHandshake).
+    ///
+    /// Defaults to 10 seconds.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.options.handshake_timeout = Some(timeout);
+        self
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use async_rustls::rustls;
+
     use super::*;
+    use crate::config::WithSecrets;
 
     const METADATA: &str = "testmeta";
     const TEST_FORWARD: &str = "testforward";
@@ -181,6 +278,84 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_serde() {
+        let options = TlsTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        }
+        .allow_cidr_string(ALLOW_CIDR)
+        .deny_cidr_string(DENY_CIDR)
+        .proxy_proto(ProxyProto::V2)
+        .metadata(METADATA)
+        .domain(DOMAIN)
+        .mutual_tlsca(CA_CERT.into())
+        .mutual_tlsca(CA_CERT2.into())
+        .key_pem(KEY.into())
+        .cert_pem(CERT.into())
+        .forwards_to(TEST_FORWARD)
+        .options;
+
+        let json = serde_json::to_string(&WithSecrets(&options)).unwrap();
+        tunnel_test(serde_json::from_str::<TlsOptions>(&json).unwrap());
+
+        let redacted = serde_json::to_value(&options).unwrap();
+        assert_eq!("********", redacted["key_pem"]);
+        assert_eq!("test cert", redacted["cert_pem"]);
+        assert!(serde_json::from_value::<TlsOptions>(redacted).is_err());
+    }
+
+    #[test]
+    fn test_termination_at_agent() {
+        let mut chain = &include_bytes!("../../examples/domain.crt")[..];
+        let mut key = &include_bytes!("../../examples/domain.key")[..];
+        let chain = rustls_pemfile::certs(&mut chain).unwrap();
+        let key = rustls_pemfile::rsa_private_keys(&mut key)
+            .unwrap()
+            .remove(0);
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                chain.into_iter().map(rustls::Certificate).collect(),
+                rustls::PrivateKey(key),
+            )
+            .unwrap();
+
+        let builder = TlsTunnelBuilder {
+            session: None,
+            options: Default::default(),
+        }
+        .key_pem(KEY.into())
+        .cert_pem(CERT.into())
+        .termination_at_agent(config);
+
+        let (_, handshake_timeout) = builder.options.termination_at_agent().unwrap();
+        assert_eq!(DEFAULT_HANDSHAKE_TIMEOUT, handshake_timeout);
+
+        match builder.options.opts() {
+            Some(BindOpts::Tls(endpoint)) => {
+                assert!(!endpoint.mutual_tls_at_agent);
+                assert!(endpoint.tls_termination.is_none());
+            }
+            _ => unreachable!(),
+        }
+
+        let options = builder
+            .handshake_timeout(Duration::from_secs(1))
+            .mutual_tls_at_agent()
+            .options;
+        let (_, handshake_timeout) = options.termination_at_agent().unwrap();
+        assert_eq!(Duration::from_secs(1), handshake_timeout);
+        match options.opts() {
+            Some(BindOpts::Tls(endpoint)) => {
+                assert!(endpoint.mutual_tls_at_agent);
+                assert!(endpoint.tls_termination.is_none());
+            }
+            _ => unreachable!(),
+        }
+    }
+
     fn tunnel_test<C>(tunnel_cfg: C)
     where
         C: TunnelConfig,
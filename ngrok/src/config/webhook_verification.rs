@@ -1,14 +1,23 @@
-use crate::internals::proto::{
-    SecretString,
-    WebhookVerification as WebhookProto,
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    config::redact::secret,
+    internals::proto::{
+        SecretString,
+        WebhookVerification as WebhookProto,
+    },
 };
 
 /// Configuration for webhook verification.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct WebhookVerification {
     /// The webhook provider
     pub(crate) provider: String,
     /// The secret for verifying webhooks from this provider.
+    #[serde(with = "secret")]
     pub(crate) secret: SecretString,
 }
 
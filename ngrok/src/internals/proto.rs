@@ -87,6 +87,7 @@ impl fmt::Debug for SecretBytes {
     }
 }
 
+/// A string that is redacted when debug-printed.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Default)]
 #[serde(transparent)]
 pub struct SecretString(String);
@@ -170,26 +171,38 @@ pub struct AuthExtra {
     pub client_type: String,
 }
 
+/// The ngrok server's response to a session authentication request.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct AuthResp {
+    /// The protocol version chosen by the server.
     pub version: String,
+    /// The ID assigned to the session.
     pub client_id: String,
+    /// Additional details about the session.
     #[serde(default)]
     pub extra: AuthRespExtra,
 }
 
 rpc_req!(Auth, AuthResp, AUTH_REQ);
 
+/// Additional details in the ngrok server's authentication response.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct AuthRespExtra {
+    /// The version of the ngrok server.
     pub version: Option<String>,
+    /// The region of the ngrok server.
     pub region: Option<String>,
+    /// An opaque token used to resume the session when reconnecting.
     pub cookie: Option<SecretString>,
+    /// The name of the account that the session belongs to.
     pub account_name: Option<String>,
+    /// The maximum duration of the session in nanoseconds, if limited.
     pub session_duration: Option<i64>,
+    /// The name of the account's plan.
     pub plan_name: Option<String>,
+    /// A message to display to the user.
     pub banner: Option<String>,
 }
 
@@ -279,13 +292,19 @@ pub struct UnbindResp {
 
 rpc_req!(Unbind, UnbindResp, UNBIND_REQ);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The header sent by the ngrok server ahead of each proxied connection.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct ProxyHeader {
+    /// The ID of the tunnel that the connection is destined for.
     pub id: String,
+    /// The address of the client that connected to the ngrok edge.
     pub client_addr: String,
+    /// The protocol of the connection at the ngrok edge.
     pub proto: String,
+    /// The type of edge that the connection arrived on.
     pub edge_type: EdgeType,
+    /// Whether the edge passed TLS through without terminating it.
     #[serde(rename = "PassthroughTLS")]
     pub passthrough_tls: bool,
 }
@@ -301,7 +320,7 @@ pub enum ReadHeaderError {
 }
 
 impl ProxyHeader {
-    pub async fn read_from_stream(
+    pub(crate) async fn read_from_stream(
         mut stream: impl AsyncRead + Unpin,
     ) -> Result<Self, ReadHeaderError> {
         let size = stream.read_i64_le().await?;
@@ -317,11 +336,17 @@ impl ProxyHeader {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The type of ngrok edge that a connection arrived on.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub enum EdgeType {
+    /// The edge type wasn't specified.
+    #[default]
     Undefined,
+    /// A TCP edge.
     Tcp,
+    /// A TLS edge.
     Tls,
+    /// An HTTPS edge.
     Https,
 }
 
@@ -338,6 +363,7 @@ impl FromStr for EdgeType {
 }
 
 impl EdgeType {
+    /// Get the wire representation of this edge type.
     pub fn as_str(self) -> &'static str {
         match self {
             EdgeType::Undefined => "0",
@@ -382,36 +408,44 @@ impl<'de> Deserialize<'de> for EdgeType {
     }
 }
 
+/// A request from the ngrok server for the agent to stop.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
 #[serde(rename_all = "PascalCase")]
-pub struct Stop;
+pub struct Stop {}
 
+/// The response to a server-initiated command.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
-pub struct StopResp {}
+pub struct CommandResp {
+    #[serde(default)]
+    pub error: String,
+}
+
+pub type StopResp = CommandResp;
 
 rpc_req!(Stop, StopResp, STOP_REQ);
 
+/// A request from the ngrok server for the agent to restart.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
 #[serde(rename_all = "PascalCase")]
-pub struct Restart;
+pub struct Restart {}
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-#[serde(rename_all = "PascalCase")]
-pub struct RestartResp {}
+pub type RestartResp = CommandResp;
 
 rpc_req!(Restart, RestartResp, RESTART_REQ);
 
+/// A request from the ngrok server for the agent to update itself.
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct Update {
+    /// The version to update to.
+    /// Empty if the latest version should be used.
     pub version: String,
+    /// Whether updating to a new major version is allowed.
     pub permit_major_version: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-#[serde(rename_all = "PascalCase")]
-pub struct UpdateResp {}
+pub type UpdateResp = CommandResp;
 
 rpc_req!(Update, UpdateResp, UPDATE_REQ);
 
@@ -431,7 +465,7 @@ rpc_req!(SrvInfo, SrvInfoResp, SRV_INFO_REQ);
 /// to use with this tunnel.
 ///
 /// [ProxyProto::None] disables PROXY protocol support.
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub enum ProxyProto {
     /// No PROXY protocol
     #[default]
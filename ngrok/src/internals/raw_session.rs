@@ -1,6 +1,9 @@
 use std::{
     collections::HashMap,
-    fmt::Debug,
+    fmt::{
+        self,
+        Debug,
+    },
     io,
     ops::{
         Deref,
@@ -10,7 +13,10 @@ use std::{
 };
 
 use muxado::{
-    heartbeat::HeartbeatConfig,
+    heartbeat::{
+        HeartbeatConfig,
+        HeartbeatCtl,
+    },
     typed::{
         StreamType,
         TypedAccept,
@@ -23,11 +29,18 @@ use muxado::{
 };
 use serde::Deserialize;
 use thiserror::Error;
-use tokio::io::{
-    AsyncRead,
-    AsyncReadExt,
-    AsyncWrite,
-    AsyncWriteExt,
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+    sync::Mutex,
+    time::{
+        timeout_at,
+        Instant,
+    },
 };
 use tracing::{
     debug,
@@ -43,19 +56,28 @@ use super::{
         BindExtra,
         BindOpts,
         BindResp,
+        CommandResp,
         ProxyHeader,
         ReadHeaderError,
+        Restart,
+        SrvInfo,
+        SrvInfoResp,
         StartTunnelWithLabel,
         StartTunnelWithLabelResp,
+        Stop,
         Unbind,
         UnbindResp,
+        Update,
         PROXY_REQ,
         RESTART_REQ,
         STOP_REQ,
         UPDATE_REQ,
         VERSION,
     },
-    rpc::RpcRequest,
+    rpc::{
+        read_json,
+        RpcRequest,
+    },
 };
 
 /// Errors arising from tunneling protocol RPC calls.
@@ -73,9 +95,112 @@ pub enum RpcError {
     /// The RPC response was invalid.
     #[error("failed to deserialize rpc response")]
     InvalidResponse(#[from] serde_json::Error),
+    /// The RPC didn't complete within its timeout.
+    #[error("rpc timed out after {0:?}")]
+    Timeout(Duration),
     /// There was an error in the RPC response.
-    #[error("rpc error response: {0}")]
-    Response(String),
+    #[error("rpc error response: {message}{}", DisplayCode(code))]
+    Response {
+        /// The ngrok error code, e.g. `ERR_NGROK_105`, if the server sent one.
+        code: Option<String>,
+        /// The error message, minus the error code.
+        message: String,
+        /// A link to the documentation for the error code.
+        docs_url: Option<String>,
+    },
+}
+
+// Displays an error code in parentheses after the message, if there is one.
+struct DisplayCode<'a>(&'a Option<String>);
+
+impl fmt::Display for DisplayCode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(code) => write!(f, " ({code})"),
+            None => Ok(()),
+        }
+    }
+}
+
+const ERROR_CODE_PREFIX: &str = "ERR_NGROK_";
+const ERROR_DOCS_BASE: &str = "https://ngrok.com/docs/errors/";
+
+// Error codes for rejected or unusable credentials.
+const AUTH_FAILURE_CODES: &[&str] = &[
+    "ERR_NGROK_105",
+    "ERR_NGROK_106",
+    "ERR_NGROK_107",
+    "ERR_NGROK_4018",
+];
+// The error code for an account's simultaneous session limit.
+const SESSION_LIMIT_CODE: &str = "ERR_NGROK_108";
+// Error codes for account limits, e.g. on sessions or tunnels.
+const QUOTA_EXCEEDED_CODES: &[&str] = &[SESSION_LIMIT_CODE, "ERR_NGROK_324"];
+
+impl RpcError {
+    /// Build a [RpcError::Response] from an error string sent by the ngrok
+    /// server, parsing out its `ERR_NGROK_NNN` code if there is one.
+    pub(crate) fn from_response(error: impl Into<String>) -> Self {
+        let error = error.into();
+        let code = error
+            .split_whitespace()
+            .find(|word| {
+                word.strip_prefix(ERROR_CODE_PREFIX)
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .map(String::from);
+        let message = match &code {
+            Some(code) => error.replacen(code.as_str(), "", 1).trim().into(),
+            None => error,
+        };
+        let docs_url = code
+            .as_ref()
+            .map(|code| format!("{ERROR_DOCS_BASE}{}", code.to_lowercase()));
+        RpcError::Response {
+            code,
+            message,
+            docs_url,
+        }
+    }
+
+    /// The ngrok error code sent by the server, if any.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            RpcError::Response { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the server rejected the session's credentials, e.g. because
+    /// the authtoken is invalid or has been revoked.
+    pub fn is_auth_failure(&self) -> bool {
+        self.code()
+            .is_some_and(|code| AUTH_FAILURE_CODES.contains(&code))
+    }
+
+    /// Whether the server refused the request because of an account limit,
+    /// e.g. on the number of sessions or tunnels.
+    pub fn is_quota_exceeded(&self) -> bool {
+        self.code()
+            .is_some_and(|code| QUOTA_EXCEEDED_CODES.contains(&code))
+    }
+
+    // Whether the server refused to start a session because the account
+    // already has as many as it's allowed.
+    pub(crate) fn is_session_limit(&self) -> bool {
+        self.code() == Some(SESSION_LIMIT_CODE)
+    }
+
+    /// Whether making the same request again might succeed.
+    ///
+    /// Transport errors and unclassified server errors are considered
+    /// retryable. Auth failures, exceeded quotas, and responses that couldn't
+    /// be understood are not.
+    pub fn is_retryable(&self) -> bool {
+        !(self.is_auth_failure()
+            || self.is_quota_exceeded()
+            || matches!(self, RpcError::InvalidResponse(_)))
+    }
 }
 
 #[derive(Error, Debug)]
@@ -95,7 +220,11 @@ pub enum AcceptError {
 }
 
 pub struct RpcClient {
-    open: Box<dyn TypedOpen + Send>,
+    // Only held long enough to open a stream, so calls can be made
+    // concurrently.
+    open: Mutex<Box<dyn TypedOpen + Send>>,
+    // The default timeout for calls.
+    timeout: Duration,
 }
 
 pub struct IncomingStreams {
@@ -105,6 +234,7 @@ pub struct IncomingStreams {
 pub struct RawSession {
     client: RpcClient,
     incoming: IncomingStreams,
+    heartbeat: HeartbeatCtl,
 }
 
 impl Deref for RawSession {
@@ -124,6 +254,7 @@ impl RawSession {
     pub async fn start<S, F>(
         io_stream: S,
         heartbeat: HeartbeatConfig<F>,
+        rpc_timeout: Duration,
     ) -> Result<Self, StartSessionError>
     where
         S: AsyncRead + AsyncWrite + Send + 'static,
@@ -132,35 +263,48 @@ impl RawSession {
         let mux_sess = SessionBuilder::new(io_stream).start();
 
         let typed = muxado::typed::Typed::new(mux_sess);
-        let (heartbeat, _) = muxado::heartbeat::Heartbeat::start(typed, heartbeat).await?;
+        let (heartbeat, ctl) = muxado::heartbeat::Heartbeat::start(typed, heartbeat).await?;
         let (open, accept) = heartbeat.split_typed();
 
         let sess = RawSession {
             client: RpcClient {
-                open: Box::new(open),
+                open: Mutex::new(Box::new(open)),
+                timeout: rpc_timeout,
             },
             incoming: IncomingStreams {
                 accept: Box::new(accept),
             },
+            heartbeat: ctl,
         };
 
         Ok(sess)
     }
 
     #[allow(dead_code)]
-    pub async fn accept(&mut self) -> Result<TunnelStream, AcceptError> {
+    pub async fn accept(&mut self) -> Result<IncomingStream, AcceptError> {
         self.incoming.accept().await
     }
 
-    pub fn split(self) -> (RpcClient, IncomingStreams) {
-        (self.client, self.incoming)
+    pub fn split(self) -> (RpcClient, IncomingStreams, HeartbeatCtl) {
+        (self.client, self.incoming, self.heartbeat)
     }
 }
 
 impl RpcClient {
     #[instrument(level = "debug", skip(self))]
-    async fn rpc<R: RpcRequest>(&mut self, req: R) -> Result<R::Response, RpcError> {
-        let mut stream = self.open.open_typed(R::TYPE).await?;
+    async fn rpc<R: RpcRequest>(
+        &self,
+        req: R,
+        timeout: Option<Duration>,
+    ) -> Result<R::Response, RpcError> {
+        let timeout = timeout.unwrap_or(self.timeout);
+        let deadline = Instant::now() + timeout;
+
+        let mut stream = timeout_at(deadline, async {
+            self.open.lock().await.open_typed(R::TYPE).await
+        })
+        .await
+        .map_err(|_| RpcError::Timeout(timeout))??;
         let s = serde_json::to_string(&req)
             // This should never happen, since we control the request types and
             // know that they will always serialize correctly. Just in case
@@ -168,16 +312,32 @@ impl RpcClient {
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
             .map_err(RpcError::Send)?;
 
-        stream
-            .write_all(s.as_bytes())
-            .await
-            .map_err(RpcError::Send)?;
-
-        let mut buf = Vec::new();
-        stream
-            .read_to_end(&mut buf)
-            .await
-            .map_err(RpcError::Receive)?;
+        let call = timeout_at(deadline, async {
+            stream
+                .write_all(s.as_bytes())
+                .await
+                .map_err(RpcError::Send)?;
+
+            let mut buf = Vec::new();
+            stream
+                .read_to_end(&mut buf)
+                .await
+                .map_err(RpcError::Receive)?;
+            Ok::<_, RpcError>(buf)
+        })
+        .await;
+
+        let buf = match call {
+            Ok(res) => res?,
+            Err(_) => {
+                // Let the server know that we've given up on the call so that
+                // the stream doesn't hang around.
+                if let Err(error) = stream.reset(MuxadoError::StreamCancelled).await {
+                    debug!(%error, "failed to reset timed out rpc stream");
+                }
+                return Err(RpcError::Timeout(timeout));
+            }
+        };
 
         #[derive(Debug, Deserialize)]
         struct ErrResp {
@@ -191,7 +351,7 @@ impl RpcClient {
         if let Ok(err) = err_resp {
             if !err.error.is_empty() {
                 debug!(?err, "decoded rpc error response");
-                return Err(RpcError::Response(err.error));
+                return Err(RpcError::from_response(err.error));
             }
         }
 
@@ -200,11 +360,22 @@ impl RpcClient {
         Ok(ok_resp?)
     }
 
+    /// Close the session, letting the server know that we're going away.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn close(&self) -> Result<(), MuxadoError> {
+        self.open
+            .lock()
+            .await
+            .close(MuxadoError::None, "session closed".into())
+            .await
+    }
+
     #[instrument(level = "debug", skip(self))]
     pub async fn auth(
-        &mut self,
+        &self,
         id: impl Into<String> + Debug,
         extra: AuthExtra,
+        timeout: Option<Duration>,
     ) -> Result<AuthResp, RpcError> {
         let id = id.into();
         let req = Auth {
@@ -213,19 +384,20 @@ impl RpcClient {
             version: vec![VERSION.into()],
         };
 
-        let resp = self.rpc(req).await?;
+        let resp = self.rpc(req, timeout).await?;
 
         Ok(resp)
     }
 
     #[instrument(level = "debug", skip(self))]
     pub async fn listen(
-        &mut self,
+        &self,
         protocol: impl Into<String> + Debug,
         opts: BindOpts,
         extra: BindExtra,
         id: impl Into<String> + Debug,
         forwards_to: impl Into<String> + Debug,
+        timeout: Option<Duration>,
     ) -> Result<BindResp<BindOpts>, RpcError> {
         // Sorry, this is awful. Serde untagged unions are pretty fraught and
         // hard to debug, so we're using this macro to specialize this call
@@ -247,7 +419,7 @@ impl RpcClient {
                             extra,
                         };
 
-                        let resp = self.rpc(req).await?;
+                        let resp = self.rpc(req, timeout).await?;
                         BindResp {
                             bind_opts: BindOpts::$var(resp.bind_opts),
                             client_id: resp.client_id,
@@ -264,10 +436,11 @@ impl RpcClient {
 
     #[instrument(level = "debug", skip(self))]
     pub async fn listen_label(
-        &mut self,
+        &self,
         labels: HashMap<String, String>,
         metadata: impl Into<String> + Debug,
         forwards_to: impl Into<String> + Debug,
+        timeout: Option<Duration>,
     ) -> Result<StartTunnelWithLabelResp, RpcError> {
         let req = StartTunnelWithLabel {
             labels,
@@ -275,42 +448,126 @@ impl RpcClient {
             forwards_to: forwards_to.into(),
         };
 
-        self.rpc(req).await
+        self.rpc(req, timeout).await
     }
 
     #[instrument(level = "debug", skip(self))]
-    pub async fn unlisten(
-        &mut self,
-        id: impl Into<String> + Debug,
-    ) -> Result<UnbindResp, RpcError> {
-        self.rpc(Unbind {
-            client_id: id.into(),
-        })
+    pub async fn srv_info(&self) -> Result<SrvInfoResp, RpcError> {
+        self.rpc(SrvInfo {}, None).await
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub async fn unlisten(&self, id: impl Into<String> + Debug) -> Result<UnbindResp, RpcError> {
+        self.rpc(
+            Unbind {
+                client_id: id.into(),
+            },
+            None,
+        )
         .await
     }
 }
 
 impl IncomingStreams {
-    pub async fn accept(&mut self) -> Result<TunnelStream, AcceptError> {
-        Ok(loop {
-            let mut stream = self.accept.accept_typed().await?;
-
-            match stream.typ() {
-                RESTART_REQ => {}
-                STOP_REQ => {}
-                UPDATE_REQ => {}
-                PROXY_REQ => {
-                    let header = ProxyHeader::read_from_stream(&mut *stream).await?;
-
-                    break TunnelStream { header, stream };
-                }
-                t => return Err(AcceptError::InvalidType(t)),
+    pub async fn accept(&mut self) -> Result<IncomingStream, AcceptError> {
+        let mut stream = self.accept.accept_typed().await?;
+
+        Ok(match stream.typ() {
+            // The request is read by whoever handles the command, so that
+            // it doesn't hold up the streams behind it.
+            RESTART_REQ | STOP_REQ | UPDATE_REQ => {
+                IncomingStream::Command(CommandStream { stream })
+            }
+            PROXY_REQ => {
+                let header = ProxyHeader::read_from_stream(&mut *stream).await?;
+
+                IncomingStream::Proxy(TunnelStream { header, stream })
             }
+            t => return Err(AcceptError::InvalidType(t)),
         })
     }
 }
 
+pub enum IncomingStream {
+    Proxy(TunnelStream),
+    Command(CommandStream),
+}
+
 pub struct TunnelStream {
     pub header: ProxyHeader,
     pub stream: TypedStream,
 }
+
+#[derive(Debug)]
+pub enum Command {
+    Stop(Stop),
+    Restart(Restart),
+    Update(Update),
+}
+
+pub struct CommandStream {
+    stream: TypedStream,
+}
+
+impl CommandStream {
+    pub async fn read_request(&mut self) -> Result<Command, io::Error> {
+        let stream = &mut self.stream;
+        Ok(match stream.typ() {
+            RESTART_REQ => Command::Restart(read_json(&mut **stream).await?),
+            STOP_REQ => Command::Stop(read_json(&mut **stream).await?),
+            UPDATE_REQ => Command::Update(read_json(&mut **stream).await?),
+            t => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid command type: {t}"),
+                ))
+            }
+        })
+    }
+
+    pub async fn respond(mut self, result: Result<(), String>) -> Result<(), io::Error> {
+        let resp = CommandResp {
+            error: result.err().unwrap_or_default(),
+        };
+        self.stream.write_all(&serde_json::to_vec(&resp)?).await?;
+        self.stream.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_response() {
+        let error = RpcError::from_response(
+            "Your account may not run more than 3 tunnels.\r\n\r\nERR_NGROK_324\r\n",
+        );
+        assert!(matches!(
+            &error,
+            RpcError::Response { code: Some(code), message, docs_url: Some(docs_url) }
+                if code == "ERR_NGROK_324"
+                    && message == "Your account may not run more than 3 tunnels."
+                    && docs_url == "https://ngrok.com/docs/errors/err_ngrok_324"
+        ));
+        assert!(error.is_quota_exceeded());
+        assert!(!error.is_auth_failure());
+        assert!(!error.is_retryable());
+        assert_eq!(
+            "rpc error response: Your account may not run more than 3 tunnels. (ERR_NGROK_324)",
+            error.to_string()
+        );
+
+        let error = RpcError::from_response("something went wrong, see ERR_NGROK_");
+        assert!(matches!(
+            &error,
+            RpcError::Response { code: None, message, docs_url: None }
+                if message == "something went wrong, see ERR_NGROK_"
+        ));
+        assert!(error.is_retryable());
+        assert_eq!(
+            "rpc error response: something went wrong, see ERR_NGROK_",
+            error.to_string()
+        );
+    }
+}
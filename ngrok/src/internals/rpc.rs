@@ -1,10 +1,17 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    io,
+};
 
 use muxado::typed::StreamType;
 use serde::{
     de::DeserializeOwned,
     Serialize,
 };
+use tokio::io::{
+    AsyncRead,
+    AsyncReadExt,
+};
 
 pub trait RpcRequest: Serialize + Debug {
     type Response: DeserializeOwned + Debug;
@@ -26,3 +33,26 @@ macro_rules! rpc_req {
         }
     };
 }
+
+/// Read a single json value from a stream.
+///
+/// The sender of a request doesn't close its end of the stream, so keep
+/// reading until we have a complete value rather than reading to EOF.
+pub async fn read_json<T: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<T, io::Error> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        match serde_json::from_slice(&buf) {
+            Ok(value) => return Ok(value),
+            Err(error) if error.is_eof() => continue,
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+        }
+    }
+}
@@ -19,6 +19,8 @@ pub mod config {
     mod common;
     pub use common::*;
 
+    mod agent;
+    pub use agent::*;
     mod headers;
     mod http;
     pub use http::*;
@@ -28,6 +30,8 @@ pub mod config {
     pub use oauth::*;
     mod oidc;
     pub use oidc::*;
+    mod redact;
+    pub use redact::WithSecrets;
     mod tcp;
     pub use tcp::*;
     mod tls;
@@ -40,8 +44,20 @@ pub mod session;
 /// Types for working with ngrok tunnels.
 pub mod tunnel;
 
+/// Types for the PROXY protocol headers that ngrok edges can send ahead of
+/// each connection.
+pub mod proxy_proto;
+
+/// Routing of tunnel connections to different backends by sniffing their
+/// first bytes.
+pub mod router;
+
 mod tunnel_ext;
 
+/// An in-process mock ngrok server for offline testing.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 #[doc(inline)]
 pub use session::Session;
 #[doc(inline)]
@@ -67,3 +83,6 @@ pub mod prelude {
 
 #[cfg(all(test, feature = "online-tests"))]
 mod online_tests;
+
+#[cfg(test)]
+mod offline_tests;
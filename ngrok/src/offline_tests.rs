@@ -0,0 +1,1962 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use anyhow::{
+    anyhow,
+    Error,
+};
+use async_rustls::{
+    rustls::{
+        self,
+        client::{
+            HandshakeSignatureValid,
+            ServerCertVerified,
+            ServerCertVerifier,
+        },
+        internal::msgs::handshake::DigitallySignedStruct,
+        server::{
+            ClientCertVerified,
+            ClientCertVerifier,
+        },
+        Certificate,
+        DistinguishedNames,
+        PrivateKey,
+        ServerName,
+    },
+    TlsAcceptor,
+    TlsConnector,
+};
+use futures::{
+    stream::BoxStream,
+    Stream,
+    StreamExt,
+    TryStreamExt,
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::TcpListener,
+    sync::mpsc,
+    test,
+    time::timeout,
+};
+use tokio_util::compat::{
+    FuturesAsyncReadCompatExt,
+    TokioAsyncReadCompatExt,
+};
+use tracing_test::traced_test;
+
+use crate::{
+    config::{
+        AgentConfig,
+        AgentConfigError,
+        AgentTunnelBuilder,
+        LabeledOptions,
+        LabeledTunnelBuilder,
+        ProxyProto,
+        TcpOptions,
+        TcpTunnelBuilder,
+    },
+    internals::proto::{
+        STOP_REQ,
+        UPDATE_REQ,
+    },
+    prelude::*,
+    proxy_proto::ProxyProtoError,
+    router::{
+        Protocol,
+        Route,
+        Router,
+    },
+    session::{
+        AuthtokenFile,
+        ConnectCallback,
+        ConnectError,
+        FixedInterval,
+        NeverReconnect,
+        Placement,
+        ReconnectError,
+        ReconnectPolicy,
+        RpcError,
+        SessionBuilder,
+        SessionEvent,
+        SessionPool,
+        Update,
+    },
+    testing::{
+        EdgeType,
+        MockServer,
+        ProxyHeader,
+        Rpc,
+    },
+    tunnel::{
+        AcceptError,
+        ForwardOptions,
+    },
+    Conn,
+    Session,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn header(id: &str) -> ProxyHeader {
+    ProxyHeader {
+        id: id.into(),
+        client_addr: "1.2.3.4:5678".into(),
+        proto: "https".into(),
+        edge_type: EdgeType::Https,
+        passthrough_tls: false,
+    }
+}
+
+async fn next_conn(tun: &mut (impl Tunnel + ?Sized)) -> Result<Conn, Error> {
+    timeout(TIMEOUT, tun.try_next())
+        .await??
+        .ok_or_else(|| anyhow!("tunnel closed"))
+}
+
+// Build a session that connects to each of the servers by its address.
+fn multi_server_builder(servers: &[(&str, &MockServer)]) -> SessionBuilder {
+    let routes = servers
+        .iter()
+        .map(|(addr, server)| (addr.to_string(), server.connect_callback()))
+        .collect::<HashMap<String, ConnectCallback>>();
+    let mut builder = Session::builder().server_addrs(servers.iter().map(|(addr, _)| *addr));
+    builder.with_connect_callback(Arc::new(move |addr, tls_config| {
+        routes[&addr](addr, tls_config)
+    }));
+    builder
+}
+
+async fn next_event(
+    events: &mut (impl Stream<Item = SessionEvent> + Unpin),
+) -> Result<SessionEvent, Error> {
+    timeout(TIMEOUT, events.next())
+        .await?
+        .ok_or_else(|| anyhow!("event stream closed"))
+}
+
+// Subscribe to a connected session's events, skipping the one for its current
+// connection.
+async fn subscribe(sess: &Session) -> Result<BoxStream<'static, SessionEvent>, Error> {
+    let mut events = sess.events();
+    match next_event(&mut events).await? {
+        SessionEvent::Connected { .. } => Ok(events),
+        other => anyhow::bail!("unexpected event: {other:?}"),
+    }
+}
+
+#[traced_test]
+#[test]
+async fn listen() -> Result<(), Error> {
+    let server = MockServer::new();
+    let tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .http_endpoint()
+        .metadata("Hello, world!")
+        .forwards_to("some application")
+        .listen()
+        .await?;
+
+    assert_eq!(format!("https://{}.mock.ngrok", tun.id()), tun.url());
+    assert_eq!("https", tun.proto());
+    assert_eq!("Hello, world!", tun.metadata());
+    assert_eq!("some application", tun.forwards_to());
+    assert_eq!(vec![tun.id().to_string()], server.tunnels());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn labeled() -> Result<(), Error> {
+    let server = MockServer::new();
+    let tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .labeled_tunnel()
+        .label("edge", "edghts_mock")
+        .listen()
+        .await?;
+
+    assert_eq!("edghts_mock", tun.labels()["edge"]);
+    assert_eq!(vec![tun.id().to_string()], server.tunnels());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn session_info() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+
+    let info = sess.info();
+    assert!(info.client_id.starts_with("sess_"));
+    assert_eq!("mock", info.region);
+    assert_eq!("mock-account", info.account_name);
+    assert_eq!("mock-plan", info.plan_name);
+    assert_eq!("mock-banner", info.banner);
+    assert_eq!(
+        Some(Duration::from_secs(8 * 60 * 60)),
+        info.session_duration
+    );
+
+    assert_eq!("mock", sess.server_info().await?.region);
+
+    server.fail_next(Rpc::SrvInfo, "no info for you");
+    assert!(matches!(
+        sess.server_info().await,
+        Err(RpcError::Response { message, code: None, .. }) if message == "no info for you"
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn auth_rejected() -> Result<(), Error> {
+    let server = MockServer::new();
+    server.reject_auth("invalid authtoken");
+
+    let res = server.session_builder().connect().await;
+
+    assert!(matches!(
+        res,
+        Err(ConnectError::Auth(RpcError::Response { message, .. })) if message == "invalid authtoken"
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn auth_rejected_code() -> Result<(), Error> {
+    let server = MockServer::new();
+    server.reject_auth("The authtoken you specified is invalid.\r\n\r\nERR_NGROK_107\r\n");
+
+    let error = match server.session_builder().connect().await {
+        Err(ConnectError::Auth(error)) => error,
+        other => anyhow::bail!("unexpected result: {:?}", other.map(|_| ())),
+    };
+
+    assert!(matches!(
+        &error,
+        RpcError::Response { code: Some(code), message, docs_url: Some(docs_url) }
+            if code == "ERR_NGROK_107"
+                && message == "The authtoken you specified is invalid."
+                && docs_url == "https://ngrok.com/docs/errors/err_ngrok_107"
+    ));
+    assert!(error.is_auth_failure());
+    assert!(!error.is_retryable());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn connection_refused() -> Result<(), Error> {
+    let server = MockServer::new();
+    server.refuse_connections(true);
+
+    let res = server.session_builder().connect().await;
+
+    assert!(matches!(res, Err(ConnectError::Tcp(_))));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn connect_report() -> Result<(), Error> {
+    let server = MockServer::new();
+    let (sess, reports) = server.session_builder().connect_with_report().await;
+    let sess = sess?;
+
+    let [report] = &reports[..] else {
+        anyhow::bail!("expected one report, got {reports:?}");
+    };
+    assert!(report.dial_duration.is_some());
+    assert!(report.auth_duration.is_some());
+    assert_eq!(
+        sess.info().client_id,
+        report.auth_response.as_ref().unwrap().client_id
+    );
+    assert!(sess.connect_report().auth_response.is_some());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn auth_timeout() -> Result<(), Error> {
+    let server = MockServer::new();
+    server.delay(Rpc::Auth, Duration::from_secs(60));
+
+    let (res, reports) = server
+        .session_builder()
+        .auth_timeout(Duration::from_millis(100))
+        .connect_with_report()
+        .await;
+    let report = &reports[0];
+
+    assert!(matches!(
+        res,
+        Err(ConnectError::Auth(RpcError::Timeout(t))) if t == Duration::from_millis(100)
+    ));
+    assert!(report.dial_duration.is_some());
+    assert!(report.auth_duration.is_none());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn handshake_timeout() -> Result<(), Error> {
+    // Accept connections, but never say anything.
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let mut conns = vec![];
+        while let Ok((conn, _)) = listener.accept().await {
+            conns.push(conn);
+        }
+    });
+
+    let mut builder = Session::builder().handshake_timeout(Duration::from_millis(100));
+    builder.with_server_addr(format!("localhost:{}", addr.port()));
+    let (res, reports) = timeout(TIMEOUT, builder.connect_with_report()).await?;
+    let report = &reports[0];
+
+    assert!(matches!(res, Err(ConnectError::HandshakeTimeout(_))));
+    assert!(report.resolved_addrs.contains(&addr));
+    assert_eq!(Some(addr), report.remote_addr);
+    assert!(report.dial_duration.is_some());
+    assert!(report.handshake_duration.is_none());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn server_failover() -> Result<(), Error> {
+    let (a, b) = (MockServer::new(), MockServer::new());
+    a.refuse_connections(true);
+
+    let (sess, reports) = multi_server_builder(&[("a:443", &a), ("b:443", &b)])
+        .connect_with_report()
+        .await;
+    let sess = sess?;
+
+    assert_eq!("b:443", sess.info().server_addr);
+    assert_eq!("b:443", sess.connect_report().server_addr);
+    // The failed attempt is reported too.
+    assert_eq!(2, reports.len());
+    assert_eq!("a:443", reports[0].server_addr);
+    assert!(reports[0].auth_response.is_none());
+    assert_eq!("b:443", reports[1].server_addr);
+    assert_eq!(1, b.sessions());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect_failover() -> Result<(), Error> {
+    let (a, b) = (MockServer::new(), MockServer::new());
+    let sess = multi_server_builder(&[("a:443", &a), ("b:443", &b)])
+        .reconnect_policy(FixedInterval::new(Duration::from_millis(10)))
+        .connect()
+        .await?;
+    let _tun = sess.tcp_endpoint().listen().await?;
+    let mut events = subscribe(&sess).await?;
+    assert_eq!("a:443", sess.info().server_addr);
+
+    a.refuse_connections(true);
+    a.drop_transports();
+
+    loop {
+        if let SessionEvent::Connected { server_addr, .. } = next_event(&mut events).await? {
+            assert_eq!("b:443", server_addr);
+            break;
+        }
+    }
+    assert_eq!("b:443", sess.info().server_addr);
+    assert_eq!(1, b.tunnels().len());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn probe_latency() -> Result<(), Error> {
+    let (a, b) = (MockServer::new(), MockServer::new());
+    a.latency(Duration::from_millis(100));
+
+    let sess = multi_server_builder(&[("a:443", &a), ("b:443", &b)])
+        .probe_latency(true)
+        .connect()
+        .await?;
+
+    assert_eq!("b:443", sess.info().server_addr);
+    // Probes don't authenticate.
+    assert!(a.auths().is_empty());
+    assert_eq!(1, b.auths().len());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn proxy_rejected() -> Result<(), Error> {
+    let proxy = TcpListener::bind("127.0.0.1:0").await?;
+    let proxy_addr = proxy.local_addr()?;
+    tokio::spawn(async move {
+        let (mut conn, _) = proxy.accept().await?;
+        let mut buf = [0u8; 1024];
+        let _ = conn.read(&mut buf).await?;
+        conn.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").await?;
+        Ok::<_, Error>(())
+    });
+
+    let res = Session::builder()
+        .proxy_url(format!("http://{proxy_addr}"))
+        .connect()
+        .await;
+
+    assert!(matches!(
+        res,
+        Err(ConnectError::ProxyRejected(status)) if status == "HTTP/1.1 403 Forbidden"
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn bind_error() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+
+    server.fail_next(Rpc::Bind, "tunnel limit reached");
+    let res = sess.tcp_endpoint().listen().await;
+    assert!(matches!(
+        res,
+        Err(RpcError::Response { message, .. }) if message == "tunnel limit reached"
+    ));
+
+    // Only the next call should fail.
+    sess.tcp_endpoint().listen().await?;
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn start_tunnels() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+
+    let tuns = sess
+        .start_tunnels((0..200).map(|i| sess.labeled_tunnel().label("edge", format!("edge_{i}"))))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (i, tun) in tuns.iter().enumerate() {
+        assert_eq!(format!("edge_{i}"), tun.labels()["edge"]);
+    }
+    assert_eq!(200, server.tunnels().len());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn concurrent_rpcs() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let slow = sess.tcp_endpoint().listen().await?;
+
+    // A slow unbind shouldn't hold up anything else.
+    server.delay(Rpc::Unbind, Duration::from_secs(60));
+    let unbind = tokio::spawn({
+        let sess = sess.clone();
+        let id = slow.id().to_string();
+        async move { sess.close_tunnel(id).await }
+    });
+
+    server.delay(Rpc::Bind, Duration::from_millis(200));
+    let res = timeout(
+        Duration::from_secs(2),
+        sess.start_tunnels((0..20).map(|_| sess.tcp_endpoint())),
+    )
+    .await?;
+    assert!(res.iter().all(Result::is_ok));
+
+    unbind.abort();
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn rpc_timeout() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .rpc_timeout(Duration::from_millis(200))
+        .connect()
+        .await?;
+
+    server.delay(Rpc::Bind, Duration::from_secs(60));
+    let res = timeout(TIMEOUT, sess.tcp_endpoint().listen()).await?;
+    assert!(matches!(res, Err(RpcError::Timeout(t)) if t == Duration::from_millis(200)));
+
+    // Tunnels can override the session's timeout.
+    server.delay(Rpc::Bind, Duration::from_millis(400));
+    sess.tcp_endpoint()
+        .listen_timeout(Duration::from_secs(2))
+        .listen()
+        .await?;
+
+    // A timed out call doesn't get in the way of later ones.
+    server.delay(Rpc::Bind, Duration::ZERO);
+    sess.tcp_endpoint().listen().await?;
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn proxy_conn() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .http_endpoint()
+        .listen()
+        .await?;
+
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    let mut conn = next_conn(&mut tun).await?;
+
+    assert_eq!("1.2.3.4:5678", conn.remote_addr().to_string());
+    assert_eq!(EdgeType::Https, conn.edge_type());
+    assert_eq!("https", conn.proto());
+    assert!(!conn.passthrough_tls());
+    assert_eq!(tun.id(), conn.info().tunnel_id());
+
+    edge.write_all(b"ping").await?;
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+
+    conn.write_all(b"pong").await?;
+    edge.read_exact(&mut buf).await?;
+    assert_eq!(b"pong", &buf);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn proxy_conn_info() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .labeled_tunnel()
+        .label("edge", "edghts_123")
+        .listen()
+        .await?;
+
+    let _edge = server
+        .push_conn(ProxyHeader {
+            client_addr: "not an address".into(),
+            proto: "tls".into(),
+            edge_type: EdgeType::Tls,
+            passthrough_tls: true,
+            ..header(&tun.id())
+        })
+        .await?;
+    let conn = next_conn(&mut tun).await?;
+
+    let info = conn.info();
+    assert_eq!("0.0.0.0:0", info.remote_addr().to_string());
+    assert_eq!("not an address", info.client_addr());
+    assert_eq!(EdgeType::Tls, info.edge_type());
+    assert_eq!("tls", info.proto());
+    assert!(info.passthrough_tls());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn proxy_proto_decoding() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tcp_endpoint()
+        .decode_proxy_proto(ProxyProto::V1)
+        .listen()
+        .await?;
+
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    edge.write_all(b"PROXY TCP4 10.0.0.1 10.0.0.2 4321 80\r\nping")
+        .await?;
+    let mut conn = next_conn(&mut tun).await?;
+
+    assert_eq!("10.0.0.1:4321", conn.remote_addr().to_string());
+    assert_eq!("1.2.3.4:5678", conn.info().client_addr());
+    let proxy_header = conn.proxy_header().unwrap();
+    assert_eq!(ProxyProto::V1, proxy_header.version());
+    assert_eq!(Some("10.0.0.2:80".parse()?), proxy_header.destination());
+
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    edge.write_all(b"GET / HTTP/1.1\r\n").await?;
+    let res = timeout(TIMEOUT, tun.next()).await?;
+    assert!(matches!(
+        res,
+        Some(Err(AcceptError::ProxyProto(ProxyProtoError::Missing)))
+    ));
+
+    // The tunnel carries on after a bad header.
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    edge.write_all(b"PROXY UNKNOWN\r\n").await?;
+    let conn = next_conn(&mut tun).await?;
+    assert_eq!("1.2.3.4:5678", conn.remote_addr().to_string());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test(start_paused = true)]
+async fn proxy_proto_timeout() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .labeled_tunnel()
+        .label("edge", "mock")
+        .decode_proxy_proto()
+        .listen()
+        .await?;
+
+    // A server-speaks-first protocol, without a header from the edge.
+    let _edge = server.push_conn(header(&tun.id())).await?;
+    let res = timeout(Duration::from_secs(60), tun.next()).await?;
+    assert!(matches!(
+        res,
+        Some(Err(AcceptError::ProxyProto(ProxyProtoError::Io(
+            io::ErrorKind::TimedOut
+        ))))
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn forward_proxy_proto() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .http_endpoint()
+        .listen()
+        .await?;
+
+    let id = tun.id();
+    let backend = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = backend.local_addr()?;
+    tokio::spawn(async move {
+        tun.forward_tcp_with(addr, ForwardOptions::default().proxy_proto(ProxyProto::V1))
+            .await
+    });
+
+    let mut edge = server.push_conn(header(&id)).await?;
+    edge.write_all(b"ping").await?;
+
+    // The destination is the default port for the tunnel's https URL.
+    let (mut local, _) = timeout(TIMEOUT, backend.accept()).await??;
+    let expected = b"PROXY TCP4 1.2.3.4 0.0.0.0 5678 443\r\nping";
+    let mut buf = vec![0u8; expected.len()];
+    timeout(TIMEOUT, local.read_exact(&mut buf)).await??;
+    assert_eq!(expected.as_slice(), buf);
+
+    // Don't make up a source address for the backend when the edge's can't
+    // be parsed.
+    let mut edge = server
+        .push_conn(ProxyHeader {
+            client_addr: "not an address".into(),
+            ..header(&id)
+        })
+        .await?;
+    edge.write_all(b"ping").await?;
+
+    let (mut local, _) = timeout(TIMEOUT, backend.accept()).await??;
+    let expected = b"PROXY UNKNOWN\r\nping";
+    let mut buf = vec![0u8; expected.len()];
+    timeout(TIMEOUT, local.read_exact(&mut buf)).await??;
+    assert_eq!(expected.as_slice(), buf);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn forward_routed() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tcp_endpoint()
+        .listen()
+        .await?;
+
+    let id = tun.id();
+    let ssh = TcpListener::bind("127.0.0.1:0").await?;
+    let fallback = TcpListener::bind("127.0.0.1:0").await?;
+    let (http_tx, mut http_rx) = mpsc::channel(1);
+    let router = Router::new()
+        .protocol(Protocol::Ssh, Route::tcp(ssh.local_addr()?.to_string()))
+        .protocol(
+            Protocol::Http,
+            Route::handler(move |mut conn| {
+                let http_tx = http_tx.clone();
+                async move {
+                    let mut buf = [0u8; 4];
+                    conn.read_exact(&mut buf).await.unwrap();
+                    http_tx.send(buf).await.unwrap();
+                }
+            }),
+        )
+        .fallback(Route::tcp(fallback.local_addr()?.to_string()))
+        .peek_timeout(Duration::from_millis(100));
+    tokio::spawn(async move { tun.forward_routed(router).await });
+
+    // The peeked bytes are replayed to the backend.
+    let mut edge = server.push_conn(header(&id)).await?;
+    edge.write_all(b"SSH-2.0-test\r\n").await?;
+    let (mut local, _) = timeout(TIMEOUT, ssh.accept()).await??;
+    let mut buf = [0u8; 14];
+    timeout(TIMEOUT, local.read_exact(&mut buf)).await??;
+    assert_eq!(b"SSH-2.0-test\r\n", &buf);
+
+    let mut edge = server.push_conn(header(&id)).await?;
+    edge.write_all(b"GET / HTTP/1.1\r\n").await?;
+    assert_eq!(Some(*b"GET "), timeout(TIMEOUT, http_rx.recv()).await?);
+
+    // Connections that don't say anything go to the fallback.
+    let _edge = server.push_conn(header(&id)).await?;
+    timeout(TIMEOUT, fallback.accept()).await??;
+
+    Ok(())
+}
+
+// Accepts any certificate and signature, since the example certificate is too
+// old for webpki.
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _: &Certificate,
+        _: &[Certificate],
+        _: &ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+    fn verify_tls13_signature(
+        &self,
+        _: &[u8],
+        _: &Certificate,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+}
+
+impl ClientCertVerifier for AcceptAnyCert {
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        Some(vec![])
+    }
+    fn verify_client_cert(
+        &self,
+        _: &Certificate,
+        _: &[Certificate],
+        _: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+    fn verify_tls13_signature(
+        &self,
+        _: &[u8],
+        _: &Certificate,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+}
+
+fn example_cert() -> Result<(Vec<Certificate>, PrivateKey), Error> {
+    let cert = rustls_pemfile::certs(&mut include_bytes!("../examples/domain.crt").as_slice())?;
+    let key =
+        rustls_pemfile::rsa_private_keys(&mut include_bytes!("../examples/domain.key").as_slice())?;
+    Ok((
+        cert.into_iter().map(Certificate).collect(),
+        PrivateKey(key.into_iter().next().ok_or_else(|| anyhow!("no key"))?),
+    ))
+}
+
+#[traced_test]
+#[test]
+async fn termination_at_agent() -> Result<(), Error> {
+    let (chain, key) = example_cert()?;
+    let mut server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(AcceptAnyCert))
+        .with_single_cert(chain.clone(), key.clone())?;
+    server_config.alpn_protocols = vec![b"h2x".to_vec()];
+
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tls_endpoint()
+        .termination_at_agent(server_config)
+        .mutual_tls_at_agent()
+        .listen()
+        .await?;
+
+    let mut client_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+        .with_single_cert(chain.clone(), key)?;
+    client_config.alpn_protocols = vec![b"h2x".to_vec()];
+    let edge = server
+        .push_conn(ProxyHeader {
+            passthrough_tls: true,
+            ..header(&tun.id())
+        })
+        .await?;
+    let client = tokio::spawn(async move {
+        let mut tls = TlsConnector::from(Arc::new(client_config))
+            .connect("rust-sdk.example.com".try_into()?, edge.compat())
+            .await?
+            .compat();
+        tls.write_all(b"ping").await?;
+        tls.flush().await?;
+        let mut buf = [0u8; 4];
+        tls.read_exact(&mut buf).await?;
+        Ok::<_, Error>(buf)
+    });
+
+    let mut conn = next_conn(&mut tun).await?;
+    let tls_info = conn.tls_info().unwrap();
+    assert_eq!(Some("rust-sdk.example.com"), tls_info.server_name());
+    assert_eq!(Some(&b"h2x"[..]), tls_info.alpn_protocol());
+    assert_eq!(chain, tls_info.peer_certificates());
+
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+    conn.write_all(b"pong").await?;
+    conn.flush().await?;
+    assert_eq!(b"pong", &timeout(TIMEOUT, client).await???);
+
+    // A failed handshake only affects its own connection.
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    edge.write_all(b"GET / HTTP/1.1\r\n\r\n").await?;
+    let res = timeout(TIMEOUT, tun.next()).await?;
+    assert!(matches!(
+        res,
+        Some(Err(error @ AcceptError::TlsHandshake(_))) if error.is_per_connection()
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test(start_paused = true)]
+async fn termination_at_agent_timeout() -> Result<(), Error> {
+    let (chain, key) = example_cert()?;
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tls_endpoint()
+        .termination_at_agent(server_config)
+        .handshake_timeout(Duration::from_secs(30))
+        .listen()
+        .await?;
+
+    // The client never starts the handshake.
+    let _edge = server
+        .push_conn(ProxyHeader {
+            passthrough_tls: true,
+            ..header(&tun.id())
+        })
+        .await?;
+    let res = timeout(Duration::from_secs(60), tun.next()).await?;
+    assert!(matches!(
+        res,
+        Some(Err(error @ AcceptError::TlsHandshake(io::ErrorKind::TimedOut)))
+            if error.is_per_connection()
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn close_tunnel() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tcp_endpoint()
+        .listen()
+        .await?;
+
+    tun.close().await?;
+
+    assert!(server.tunnels().is_empty());
+    assert!(tun.try_next().await?.is_none());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect() -> Result<(), Error> {
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tcp_endpoint()
+        .listen()
+        .await?;
+
+    server.drop_transports();
+
+    timeout(TIMEOUT, server.wait_for_connections(2)).await?;
+    timeout(TIMEOUT, server.wait_for_tunnel(tun.id())).await?;
+
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    let mut conn = next_conn(&mut tun).await?;
+
+    edge.write_all(b"still here").await?;
+    let mut buf = [0u8; 10];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"still here", &buf);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn forward_tcp() -> Result<(), Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        let (mut rx, mut tx) = stream.split();
+        tokio::io::copy(&mut rx, &mut tx).await?;
+        Result::<_, Error>::Ok(())
+    });
+
+    let server = MockServer::new();
+    let mut tun = server
+        .session_builder()
+        .connect()
+        .await?
+        .tcp_endpoint()
+        .listen()
+        .await?;
+    let id = tun.id().to_string();
+
+    tokio::spawn(async move { tun.forward_tcp(local_addr).await });
+
+    let mut edge = server.push_conn(header(&id)).await?;
+    edge.write_all(b"echo").await?;
+    let mut buf = [0u8; 4];
+    timeout(TIMEOUT, edge.read_exact(&mut buf)).await??;
+    assert_eq!(b"echo", &buf);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect_events() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let tun = sess.tcp_endpoint().listen().await?;
+    let labeled = sess.labeled_tunnel().label("edge", "mock").listen().await?;
+    let labeled_id = labeled.id();
+
+    // New subscribers hear about the current connection first.
+    let mut events = sess.events();
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { client_id, .. } if client_id == sess.info().client_id
+    ));
+
+    server.drop_transports();
+
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Disconnected { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Reconnecting { attempt: 1, .. }
+    ));
+    // The connection is reported before the tunnels that it rebound.
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { region, .. } if region == "mock"
+    ));
+
+    let mut rebound = Vec::new();
+    for _ in 0..2 {
+        match next_event(&mut events).await? {
+            SessionEvent::TunnelRebound {
+                old_id,
+                new_id,
+                url,
+            } => rebound.push((old_id, new_id, url)),
+            other => anyhow::bail!("unexpected event: {other:?}"),
+        }
+    }
+    assert!(rebound.contains(&(tun.id(), tun.id(), tun.url())));
+    assert!(rebound
+        .iter()
+        .any(|(old, new, url)| *old == labeled_id && *new != labeled_id && url.is_empty()));
+
+    // Closed sessions don't claim to be connected.
+    sess.close().await?;
+    let mut events = sess.events();
+    assert!(!matches!(
+        timeout(Duration::from_millis(100), events.next()).await,
+        Ok(Some(SessionEvent::Connected { .. }))
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn rebound_tunnel() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let mut tun = sess.labeled_tunnel().label("edge", "mock").listen().await?;
+    let old_id = tun.id();
+
+    server.drop_transports();
+
+    // Labeled tunnels get a new ID when they're rebound.
+    assert_eq!(Some(String::new()), timeout(TIMEOUT, tun.rebound()).await?);
+    assert_ne!(old_id, tun.id());
+    assert_eq!(vec![tun.id()], server.tunnels());
+
+    // The handle should still be able to reach the tunnel.
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    let mut conn = next_conn(&mut tun).await?;
+    edge.write_all(b"ping").await?;
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+
+    // And close it.
+    tun.close().await?;
+    assert!(server.tunnels().is_empty());
+    assert_eq!(None, tun.rebound().await);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn never_reconnect() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .reconnect_policy(NeverReconnect)
+        .connect()
+        .await?;
+    let mut tun = sess.tcp_endpoint().listen().await?;
+    let mut events = subscribe(&sess).await?;
+
+    server.drop_transports();
+
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Disconnected { .. }
+    ));
+    assert!(matches!(
+        next_event(&mut events).await?,
+        SessionEvent::GaveUp { error } if matches!(*error, ReconnectError::Transport(_))
+    ));
+    assert!(timeout(TIMEOUT, tun.try_next()).await?.is_err());
+    assert_eq!(1, server.connections());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect_stops_on_auth_failure() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .reconnect_policy(FixedInterval::new(Duration::from_millis(10)))
+        .connect()
+        .await?;
+    let mut events = subscribe(&sess).await?;
+
+    server.reject_auth("authtoken revoked\n\nERR_NGROK_107");
+    server.drop_transports();
+
+    loop {
+        if let SessionEvent::GaveUp { error } = next_event(&mut events).await? {
+            assert!(!error.is_retryable());
+            break;
+        }
+    }
+    assert_eq!(2, server.connections());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect_policy_sees_error() -> Result<(), Error> {
+    let server = MockServer::new();
+    let fixed = FixedInterval::new(Duration::from_millis(10));
+    let sess = server
+        .session_builder()
+        .reconnect_policy(move |attempt, error: &ReconnectError| match error {
+            ReconnectError::Connect(ConnectError::Auth(_)) => None,
+            _ => fixed.next_delay(attempt, error),
+        })
+        .connect()
+        .await?;
+    let mut events = subscribe(&sess).await?;
+
+    server.reject_auth("authtoken revoked");
+    server.drop_transports();
+
+    loop {
+        if let SessionEvent::GaveUp { error } = next_event(&mut events).await? {
+            assert!(matches!(
+                &*error,
+                ReconnectError::Connect(ConnectError::Auth(RpcError::Response { message, .. }))
+                    if message == "authtoken revoked"
+            ));
+            break;
+        }
+    }
+    assert_eq!(2, server.connections());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn reconnect_retries_session_limit() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .reconnect_policy(FixedInterval::new(Duration::from_millis(10)))
+        .connect()
+        .await?;
+    let mut events = subscribe(&sess).await?;
+
+    // The server hasn't noticed that the old session is gone yet.
+    server.reject_auth(
+        "Your account is limited to 1 simultaneous ngrok agent sessions.\n\nERR_NGROK_108",
+    );
+    server.drop_transports();
+    timeout(TIMEOUT, server.wait_for_connections(3)).await?;
+    server.accept_auth();
+
+    loop {
+        match next_event(&mut events).await? {
+            SessionEvent::Connected { .. } => break,
+            SessionEvent::GaveUp { error } => anyhow::bail!("gave up: {error}"),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn rebind_failure_closes_tunnel() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let mut tuns = vec![
+        sess.tcp_endpoint().listen().await?,
+        sess.tcp_endpoint().listen().await?,
+    ];
+    let mut events = subscribe(&sess).await?;
+
+    server.fail_next(
+        Rpc::Bind,
+        "Your account may not run more than 1 tunnels.\n\nERR_NGROK_324",
+    );
+    server.drop_transports();
+
+    let failed = loop {
+        match next_event(&mut events).await? {
+            SessionEvent::TunnelRebindFailed { id, error } => {
+                assert!(error.is_quota_exceeded());
+                break id;
+            }
+            SessionEvent::GaveUp { error } => anyhow::bail!("gave up: {error}"),
+            _ => {}
+        }
+    };
+
+    // Only the tunnel that couldn't be rebound is closed.
+    let i = tuns.iter().position(|tun| tun.id() == failed).unwrap();
+    let mut failed = tuns.remove(i);
+    let mut tun = tuns.remove(0);
+    assert!(matches!(
+        timeout(TIMEOUT, failed.try_next()).await?,
+        Err(AcceptError::Rebind)
+    ));
+    assert_eq!(vec![tun.id()], server.tunnels());
+
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    let mut conn = next_conn(&mut tun).await?;
+    edge.write_all(b"ping").await?;
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn commands_unsupported() -> Result<(), Error> {
+    let server = MockServer::new();
+    let _sess = server.session_builder().connect().await?;
+
+    let extra = &server.auths()[0].extra;
+    for unsupported in [
+        &extra.stop_unsupported_error,
+        &extra.restart_unsupported_error,
+        &extra.update_unsupported_error,
+    ] {
+        assert!(!unsupported.as_deref().unwrap_or_default().is_empty());
+    }
+
+    assert!(timeout(TIMEOUT, server.stop()).await??.is_err());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn commands() -> Result<(), Error> {
+    let server = MockServer::new();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let _sess = server
+        .session_builder()
+        .handle_stop_command(move |_sess, _req| {
+            let tx = tx.clone();
+            async move {
+                tx.send(()).map_err(|e| e.to_string())?;
+                Ok(())
+            }
+        })
+        .handle_restart_command(|_sess, _req| async { Err("can't restart".into()) })
+        .handle_update_command(|_sess, req: Update| async move {
+            if req.version == "1.2.3" && !req.permit_major_version {
+                Ok(())
+            } else {
+                Err(format!("unexpected update request: {req:?}"))
+            }
+        })
+        .connect()
+        .await?;
+
+    let extra = &server.auths()[0].extra;
+    assert_eq!(Some(""), extra.stop_unsupported_error.as_deref());
+    assert_eq!(Some(""), extra.restart_unsupported_error.as_deref());
+    assert_eq!(Some(""), extra.update_unsupported_error.as_deref());
+
+    timeout(TIMEOUT, server.stop())
+        .await??
+        .map_err(Error::msg)?;
+    assert!(rx.try_recv().is_ok());
+
+    assert_eq!(
+        Err("can't restart".to_string()),
+        timeout(TIMEOUT, server.restart()).await??
+    );
+
+    timeout(TIMEOUT, server.update("1.2.3", false))
+        .await??
+        .map_err(Error::msg)?;
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn invalid_commands() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .handle_update_command(|_sess, _req| async { Ok(()) })
+        .rpc_timeout(Duration::from_millis(100))
+        .connect()
+        .await?;
+    let mut tun = sess.tcp_endpoint().listen().await?;
+
+    // Malformed requests are reported back to the server.
+    let res = timeout(TIMEOUT, server.raw_command(UPDATE_REQ, b"\"1.2.3\"")).await??;
+    assert!(matches!(res, Err(error) if error.starts_with("invalid command request")));
+
+    // A request that's never finished doesn't hold up other connections.
+    let stalled = tokio::spawn({
+        let server = server.clone();
+        async move { server.raw_command(STOP_REQ, b"{").await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let mut edge = server.push_conn(header(&tun.id())).await?;
+    let mut conn = next_conn(&mut tun).await?;
+    edge.write_all(b"ping").await?;
+    let mut buf = [0u8; 4];
+    conn.read_exact(&mut buf).await?;
+    assert_eq!(b"ping", &buf);
+
+    let res = timeout(TIMEOUT, stalled).await???;
+    assert_eq!(Err("timed out reading command request".to_string()), res);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn close_session() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let mut tun = sess.tcp_endpoint().listen().await?;
+    let mut labeled = sess.labeled_tunnel().label("edge", "mock").listen().await?;
+    let mut events = subscribe(&sess).await?;
+
+    sess.close().await?;
+
+    assert!(server.tunnels().is_empty());
+    assert!(matches!(
+        next_conn(&mut tun).await,
+        Err(e) if matches!(e.downcast_ref(), Some(AcceptError::SessionClosed))
+    ));
+    assert!(tun.try_next().await?.is_none());
+    assert!(matches!(
+        labeled.try_next().await,
+        Err(AcceptError::SessionClosed)
+    ));
+    timeout(TIMEOUT, server.wait_for_disconnect()).await?;
+
+    // Closing doesn't trigger a reconnect, and can be repeated.
+    assert!(timeout(Duration::from_millis(100), events.next())
+        .await
+        .is_err());
+    assert_eq!(1, server.connections());
+    sess.close().await?;
+    assert!(sess.tcp_endpoint().listen().await.is_err());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn drop_session() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+    let tun = sess.tcp_endpoint().listen().await?;
+
+    drop(sess);
+    // The tunnel is holding on to the session.
+    assert_eq!(1, server.sessions());
+
+    drop(tun);
+    timeout(TIMEOUT, server.wait_for_disconnect()).await?;
+    assert!(server.tunnels().is_empty());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn heartbeat() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+
+    let latency = timeout(TIMEOUT, sess.heartbeat()).await??;
+    assert_eq!(Some(latency), sess.latency());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn heartbeat_settings() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server
+        .session_builder()
+        .heartbeat_interval(Duration::from_millis(10))
+        .heartbeat_tolerance(Duration::from_secs(1))
+        .connect()
+        .await?;
+
+    let extra = &server.auths()[0].extra;
+    assert_eq!(10_000_000, extra.heartbeat_interval);
+    assert_eq!(1_000_000_000, extra.heartbeat_tolerance);
+
+    // Heartbeats are sent on their own.
+    let mut latency = sess.watch_latency();
+    timeout(TIMEOUT, latency.wait_for(Option::is_some)).await??;
+
+    // Changes made at runtime survive a reconnect.
+    let mut events = subscribe(&sess).await?;
+    sess.set_heartbeat_interval(Duration::from_millis(20));
+    sess.set_heartbeat_tolerance(Duration::from_secs(2));
+    server.drop_transports();
+    while !matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { .. }
+    ) {}
+
+    let extra = &server.auths()[1].extra;
+    assert_eq!(20_000_000, extra.heartbeat_interval);
+    assert_eq!(2_000_000_000, extra.heartbeat_tolerance);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn pool_round_robin() -> Result<(), Error> {
+    let server = MockServer::new();
+    let pool = SessionPool::builder(server.session_builder())
+        .size(3)
+        .connect()
+        .await?;
+    assert_eq!(3, server.sessions());
+
+    let mut ids = vec![];
+    for _ in 0..3 {
+        let sess = pool.next_session().await;
+        sess.tcp_endpoint().listen().await?;
+        ids.push(sess.info().client_id);
+    }
+    ids.sort();
+    ids.dedup();
+    assert_eq!(3, ids.len());
+
+    pool.close().await?;
+    timeout(TIMEOUT, server.wait_for_disconnect()).await?;
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn pool_least_loaded() -> Result<(), Error> {
+    let server = MockServer::new();
+    let pool = SessionPool::builder(server.session_builder())
+        .placement(Placement::LeastLoaded)
+        .connect()
+        .await?;
+
+    let busy = pool.next_session().await;
+    busy.tcp_endpoint().listen().await?;
+    busy.tcp_endpoint().listen().await?;
+
+    for _ in 0..2 {
+        let sess = pool.next_session().await;
+        assert_ne!(busy.info().client_id, sess.info().client_id);
+    }
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn pool_redundant_tunnel() -> Result<(), Error> {
+    let server = MockServer::new();
+    let pool = SessionPool::builder(server.session_builder())
+        .size(3)
+        .connect()
+        .await?;
+
+    let mut tun = pool
+        .listen_redundant(pool.sessions()[0].labeled_tunnel().label("edge", "mock"), 2)
+        .await?;
+    let ids = tun.ids();
+    assert_eq!(2, ids.len());
+    assert_eq!(2, server.tunnels().len());
+
+    for id in &ids {
+        let mut edge = server.push_conn(header(id)).await?;
+        let mut conn = timeout(TIMEOUT, tun.try_next())
+            .await??
+            .ok_or_else(|| anyhow!("tunnel closed"))?;
+        edge.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        timeout(TIMEOUT, conn.read_exact(&mut buf)).await??;
+        assert_eq!(b"ping", &buf);
+    }
+
+    tun.close().await?;
+    assert!(server.tunnels().is_empty());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn pool_endpoints() -> Result<(), Error> {
+    let server = MockServer::new();
+    let pool = SessionPool::builder(server.session_builder())
+        .placement(Placement::LeastLoaded)
+        .connect()
+        .await?;
+
+    pool.tcp_endpoint().await.listen().await?;
+    pool.http_endpoint().await.listen().await?;
+    for sess in pool.sessions() {
+        assert_eq!(1, sess.tunnel_count().await);
+    }
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn pool_forward_redundant() -> Result<(), Error> {
+    let server = MockServer::new();
+    let pool = SessionPool::builder(server.session_builder())
+        .connect()
+        .await?;
+
+    let mut tun = pool
+        .listen_redundant(pool.sessions()[0].labeled_tunnel().label("edge", "mock"), 2)
+        .await?;
+    assert_eq!(tun.ids()[0], tun.id());
+    assert_eq!(Some(&"mock".to_string()), tun.labels().get("edge"));
+    let ids = tun.ids();
+
+    let backend = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = backend.local_addr()?;
+    tokio::spawn(async move { tun.forward_tcp(addr).await });
+
+    for id in &ids {
+        let mut edge = server.push_conn(header(id)).await?;
+        edge.write_all(b"ping").await?;
+        let (mut local, _) = timeout(TIMEOUT, backend.accept()).await??;
+        let mut buf = [0u8; 4];
+        timeout(TIMEOUT, local.read_exact(&mut buf)).await??;
+        assert_eq!(b"ping", &buf);
+    }
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn authtoken_provider_refresh() -> Result<(), Error> {
+    let server = MockServer::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let provider_calls = calls.clone();
+    let sess = server
+        .session_builder()
+        .authtoken_provider(move || {
+            let n = provider_calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok(format!("token-{n}")) }
+        })
+        .connect()
+        .await?;
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!("token-1", server.auths()[0].extra.auth_token.as_str());
+
+    // A rejected token is refreshed once on the next connect.
+    let mut events = subscribe(&sess).await?;
+    server.fail_next(Rpc::Auth, "authtoken revoked\n\nERR_NGROK_107");
+    server.drop_transports();
+    while !matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { .. }
+    ) {}
+    assert_eq!(3, calls.load(Ordering::SeqCst));
+    assert_eq!("token-3", server.auths()[1].extra.auth_token.as_str());
+
+    // But not more than once.
+    server.reject_auth("authtoken revoked\n\nERR_NGROK_107");
+    let connections = server.connections();
+    let res = server
+        .session_builder()
+        .authtoken_provider(|| async { Ok("stale".to_string()) })
+        .connect()
+        .await;
+    assert!(matches!(res, Err(ConnectError::Auth(_))));
+    assert_eq!(connections + 2, server.connections());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn authtoken_file() -> Result<(), Error> {
+    let server = MockServer::new();
+    let path = std::env::temp_dir().join(format!("ngrok-authtoken-{}", std::process::id()));
+    tokio::fs::write(&path, "first\n").await?;
+
+    let sess = server
+        .session_builder()
+        .authtoken_provider(AuthtokenFile::new(&path))
+        .connect()
+        .await?;
+    assert_eq!("first", server.auths()[0].extra.auth_token.as_str());
+
+    // The file is re-read on reconnect.
+    tokio::fs::write(&path, "second\n").await?;
+    let mut events = subscribe(&sess).await?;
+    server.drop_transports();
+    while !matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { .. }
+    ) {}
+    assert_eq!("second", server.auths()[1].extra.auth_token.as_str());
+
+    tokio::fs::remove_file(&path).await?;
+    let res = server
+        .session_builder()
+        .authtoken_provider(AuthtokenFile::new(&path))
+        .connect()
+        .await;
+    assert!(matches!(res, Err(ConnectError::Authtoken(_))));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn root_cas_file() -> Result<(), Error> {
+    let server = MockServer::new();
+    let path = std::env::temp_dir().join(format!("ngrok-root-cas-{}.pem", std::process::id()));
+    tokio::fs::write(&path, include_bytes!("../examples/domain.crt")).await?;
+
+    server
+        .session_builder()
+        .root_cas_file(&path)
+        .connect()
+        .await?;
+
+    tokio::fs::remove_file(&path).await?;
+    let res = server
+        .session_builder()
+        .root_cas_file(&path)
+        .connect()
+        .await;
+    assert!(matches!(res, Err(error @ ConnectError::TlsConfig(_)) if !error.is_retryable()));
+
+    Ok(())
+}
+
+// Serve the mock server over TLS on a local port, with a certificate for
+// localhost.
+async fn tls_mock_server(server: &MockServer) -> Result<u16, Error> {
+    let chain =
+        rustls_pemfile::certs(&mut include_bytes!("session/testdata/server.crt").as_slice())?;
+    let key = rustls_pemfile::pkcs8_private_keys(
+        &mut include_bytes!("session/testdata/server.key").as_slice(),
+    )?;
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            chain.into_iter().map(Certificate).collect(),
+            PrivateKey(key.into_iter().next().ok_or_else(|| anyhow!("no key"))?),
+        )?;
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    // The mock server doesn't look at the TLS config it's given.
+    let client_config = Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth(),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let connect = server.connect_callback();
+    tokio::spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            let transport = connect(String::new(), client_config.clone());
+            tokio::spawn(async move {
+                let Ok(tls) = acceptor.accept(tcp.compat()).await else {
+                    return;
+                };
+                let Ok(mut transport) = transport.await else {
+                    return;
+                };
+                let _ = tokio::io::copy_bidirectional(&mut tls.compat(), &mut transport).await;
+            });
+        }
+    });
+    Ok(port)
+}
+
+#[traced_test]
+#[test]
+async fn pinned_certificates() -> Result<(), Error> {
+    let server = MockServer::new();
+    let port = tls_mock_server(&server).await?;
+    let server_cert =
+        rustls_pemfile::certs(&mut include_bytes!("session/testdata/server.crt").as_slice())?
+            .remove(0);
+    let cert_hash: [u8; 32] = ring::digest::digest(&ring::digest::SHA256, &server_cert)
+        .as_ref()
+        .try_into()?;
+    // The SHA-256 of the server certificate's SubjectPublicKeyInfo.
+    let public_key_hash = [
+        52, 161, 11, 0, 130, 117, 248, 230, 142, 109, 56, 239, 137, 99, 37, 21, 1, 74, 54, 193,
+        131, 67, 153, 162, 177, 180, 93, 232, 136, 54, 184, 188,
+    ];
+    let builder = || {
+        let mut builder = Session::builder().root_cas_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/session/testdata/ca.crt"
+        ));
+        builder.with_server_addr(format!("localhost:{port}"));
+        builder
+    };
+
+    let sess = builder()
+        .pin_certificate_sha256(cert_hash)
+        .connect()
+        .await?;
+    assert_eq!(1, server.sessions());
+    sess.close().await?;
+
+    builder()
+        .pin_public_key_sha256(public_key_hash)
+        .connect()
+        .await?;
+
+    let res = builder().pin_public_key_sha256([0; 32]).connect().await;
+    assert!(matches!(res, Err(ConnectError::Tls(_))), "{:?}", res.err());
+
+    // The pin is checked on top of the usual verification.
+    let mut builder = Session::builder().pin_certificate_sha256(cert_hash);
+    builder.with_server_addr(format!("localhost:{port}"));
+    let res = builder.connect().await;
+    assert!(matches!(res, Err(ConnectError::Tls(_))), "{:?}", res.err());
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn client_certificate_files() -> Result<(), Error> {
+    let server = MockServer::new();
+    let dir = std::env::temp_dir();
+    let cert = dir.join(format!("ngrok-client-cert-{}.pem", std::process::id()));
+    let key = dir.join(format!("ngrok-client-key-{}.pem", std::process::id()));
+    tokio::fs::write(&cert, include_bytes!("../examples/domain.crt")).await?;
+    tokio::fs::write(&key, include_bytes!("../examples/domain.key")).await?;
+
+    let sess = server
+        .session_builder()
+        .client_certificate_files(&cert, &key)
+        .reconnect_policy(FixedInterval::new(Duration::from_millis(100)))
+        .connect()
+        .await?;
+    assert!(server.auths()[0].extra.mutual_tls);
+
+    // The files are re-read on reconnect, so they can be rotated.
+    tokio::fs::write(&cert, include_bytes!("session/testdata/server.crt")).await?;
+    tokio::fs::write(&key, include_bytes!("session/testdata/server.key")).await?;
+    let mut events = subscribe(&sess).await?;
+    server.drop_transports();
+    while !matches!(
+        next_event(&mut events).await?,
+        SessionEvent::Connected { .. }
+    ) {}
+    assert_eq!(2, server.auths().len());
+    assert!(server.auths()[1].extra.mutual_tls);
+
+    // An unusable key is a configuration error, so it isn't retried.
+    tokio::fs::write(&key, "rotating").await?;
+    server.drop_transports();
+    let error = loop {
+        if let SessionEvent::GaveUp { error } = next_event(&mut events).await? {
+            break error;
+        }
+    };
+    assert!(matches!(
+        &*error,
+        ReconnectError::Connect(ConnectError::TlsConfig(_))
+    ));
+
+    tokio::fs::remove_file(&cert).await?;
+    tokio::fs::remove_file(&key).await?;
+
+    server.session_builder().connect().await?;
+    assert!(!server.auths()[2].extra.mutual_tls);
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn agent_config() -> Result<(), Error> {
+    let server = MockServer::new();
+    let config = AgentConfig::from_yaml(
+        r#"
+version: "2"
+authtoken: config-token
+heartbeat_interval: 15s
+web_addr: localhost:4040
+tunnels:
+  website:
+    proto: http
+    addr: 8080
+    domain: app.example.com
+    basic_auth: ["user:pass"]
+    request_header:
+      add: ["x-from: agent"]
+  ssh:
+    proto: tcp
+    addr: localhost:22
+    remote_addr: 1.tcp.ngrok.io:12345
+  edge:
+    labels: ["edge=edghts_mock"]
+    addr: https://localhost:8443
+"#,
+    )?;
+    assert_eq!(vec!["website", "ssh", "edge"], config.tunnel_names());
+
+    let sess = config.apply(server.session_builder()).connect().await?;
+    assert_eq!("config-token", server.auths()[0].extra.auth_token.as_str());
+    assert_eq!(15_000_000_000, server.auths()[0].extra.heartbeat_interval);
+
+    let tunnels = config.tunnels(&sess)?;
+    assert_eq!(Some("8080"), tunnels[0].addr.as_deref());
+    let AgentTunnelBuilder::Http(http) = &tunnels[0].builder else {
+        panic!("expected an http tunnel");
+    };
+    assert_eq!("8080", http.listen().await?.forwards_to());
+    assert!(matches!(tunnels[1].builder, AgentTunnelBuilder::Tcp(_)));
+    let AgentTunnelBuilder::Labeled(labeled) = &tunnels[2].builder else {
+        panic!("expected a labeled tunnel");
+    };
+    assert_eq!("edghts_mock", labeled.listen().await?.labels()["edge"]);
+
+    let config = AgentConfig::from_yaml(
+        "tunnels:
+  ssh:
+    proto: tcp
+    addr: 22
+    domain: ssh.example.com
+",
+    )?;
+    let res = config.tunnels(&sess);
+    assert!(
+        matches!(&res, Err(AgentConfigError::UnsupportedKey { section, key })
+            if section == "tunnels.ssh" && key == "domain"),
+        "{:?}",
+        res.err()
+    );
+
+    let config = AgentConfig::from_yaml(
+        "tunnels:
+  web:
+    proto: http
+    host_header: rewrite
+",
+    )?;
+    assert!(matches!(
+        config.tunnels(&sess),
+        Err(AgentConfigError::UnsupportedKey { key, .. }) if key == "host_header"
+    ));
+
+    Ok(())
+}
+
+#[traced_test]
+#[test]
+async fn from_serialized_options() -> Result<(), Error> {
+    let server = MockServer::new();
+    let sess = server.session_builder().connect().await?;
+
+    let tcp = sess.tcp_endpoint().metadata("tcp meta");
+    let options: TcpOptions = serde_json::from_str(&serde_json::to_string(tcp.options())?)?;
+    let tun = TcpTunnelBuilder::from_options(sess.clone(), options)
+        .listen()
+        .await?;
+    assert_eq!("tcp meta", tun.metadata());
+
+    let labeled = sess.labeled_tunnel().label("edge", "mock");
+    let options: LabeledOptions = serde_json::from_str(&serde_json::to_string(labeled.options())?)?;
+    let tun = LabeledTunnelBuilder::from_options(sess.clone(), options)
+        .listen()
+        .await?;
+    assert_eq!(Some(&"mock".to_string()), tun.labels().get("edge"));
+
+    Ok(())
+}
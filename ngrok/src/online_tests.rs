@@ -126,7 +126,7 @@ async fn serve_http(
 }
 
 fn start_http_server(tun: impl UrlTunnel, router: Router) -> TunnelGuard {
-    let url = tun.url().into();
+    let url = tun.url();
 
     let (tx, rx) = oneshot::channel::<()>();
 
@@ -444,10 +444,10 @@ proxy_proto_test!(
     [(V1, &b"PROXY TCP4"[..]), (V2, &b"\x0D\x0A\x0D\x0A\x00\x0D\x0A\x51\x55\x49\x54\x0A"[..])]
     [
         (http, |tun| {
-            reqwest::get(tun.url().to_string())
+            reqwest::get(tun.url())
         }),
         (tcp, |tun| {
-            reqwest::get(tun.url().to_string().replacen("tcp", "http", 1))
+            reqwest::get(tun.url().replacen("tcp", "http", 1))
         })
     ]
 );
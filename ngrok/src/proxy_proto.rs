@@ -0,0 +1,471 @@
+use std::{
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+};
+
+use bytes::Bytes;
+use thiserror::Error;
+use tokio::io::{
+    AsyncRead,
+    AsyncReadExt,
+};
+
+use crate::config::ProxyProto;
+
+// The first bytes of every v2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// The first bytes of every v1 header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+// The longest that a v1 header can be, including its CRLF.
+const V1_MAX_LEN: usize = 107;
+
+const V2_LOCAL: u8 = 0x20;
+const V2_PROXY: u8 = 0x21;
+
+const AF_UNSPEC: u8 = 0x0;
+const AF_INET: u8 = 0x1;
+const AF_INET6: u8 = 0x2;
+const AF_UNIX: u8 = 0x3;
+
+/// Errors arising when reading a PROXY protocol header from a connection.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtoError {
+    /// The header couldn't be read from the connection.
+    #[error("error reading PROXY protocol header: {0}")]
+    Io(io::ErrorKind),
+    /// The connection didn't start with a PROXY protocol header.
+    #[error("connection did not start with a PROXY protocol header")]
+    Missing,
+    /// The v1 header was malformed.
+    #[error("invalid PROXY protocol v1 header")]
+    InvalidV1,
+    /// The v2 header had an unknown version, command, or address family.
+    #[error("unsupported PROXY protocol v2 header")]
+    UnsupportedV2,
+    /// The v2 header was too short for its address family.
+    #[error("invalid PROXY protocol v2 addresses")]
+    InvalidAddresses,
+    /// The v2 header's TLVs overran its length.
+    #[error("invalid PROXY protocol v2 TLVs")]
+    InvalidTlvs,
+}
+
+impl From<io::Error> for ProxyProtoError {
+    fn from(error: io::Error) -> Self {
+        ProxyProtoError::Io(error.kind())
+    }
+}
+
+/// A type-length-value field from a PROXY protocol v2 header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tlv {
+    kind: u8,
+    value: Bytes,
+}
+
+impl Tlv {
+    /// The type of this field, e.g. `0x01` for ALPN.
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    /// The raw value of this field.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+/// A PROXY protocol header, sent by the ngrok edge ahead of a connection's
+/// data when the tunnel is configured with a [ProxyProto] version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyProtoHeader {
+    version: ProxyProto,
+    addrs: Option<(SocketAddr, SocketAddr)>,
+    tlvs: Vec<Tlv>,
+}
+
+impl ProxyProtoHeader {
+    /// Create a header for a connection from `source` to `destination`.
+    ///
+    /// If only one of the addresses is IPv6, the other is sent as an
+    /// IPv4-mapped IPv6 address.
+    pub fn new(version: ProxyProto, source: SocketAddr, destination: SocketAddr) -> Self {
+        let addrs = match (source, destination) {
+            (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => {
+                (source, destination)
+            }
+            _ => (to_ipv6(source), to_ipv6(destination)),
+        };
+        ProxyProtoHeader {
+            version,
+            addrs: Some(addrs),
+            tlvs: vec![],
+        }
+    }
+
+    /// Create a header for a connection whose addresses aren't known.
+    ///
+    /// This is encoded as a v1 `UNKNOWN` or v2 `LOCAL` header, so that the
+    /// receiver falls back to the addresses of the connection itself.
+    pub fn unknown(version: ProxyProto) -> Self {
+        ProxyProtoHeader {
+            version,
+            addrs: None,
+            tlvs: vec![],
+        }
+    }
+
+    /// Encode the header to send ahead of a connection's data.
+    ///
+    /// This is empty for [ProxyProto::None].
+    pub fn encode(&self) -> Vec<u8> {
+        match self.version {
+            ProxyProto::None => vec![],
+            ProxyProto::V1 => match self.addrs {
+                Some((source, destination)) => format!(
+                    "PROXY {} {} {} {} {}\r\n",
+                    if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                    source.ip(),
+                    destination.ip(),
+                    source.port(),
+                    destination.port(),
+                )
+                .into_bytes(),
+                None => b"PROXY UNKNOWN\r\n".to_vec(),
+            },
+            ProxyProto::V2 => {
+                let mut body = vec![];
+                let family = match self.addrs {
+                    Some((SocketAddr::V4(source), SocketAddr::V4(destination))) => {
+                        body.extend(source.ip().octets());
+                        body.extend(destination.ip().octets());
+                        body.extend(source.port().to_be_bytes());
+                        body.extend(destination.port().to_be_bytes());
+                        AF_INET
+                    }
+                    Some((SocketAddr::V6(source), SocketAddr::V6(destination))) => {
+                        body.extend(source.ip().octets());
+                        body.extend(destination.ip().octets());
+                        body.extend(source.port().to_be_bytes());
+                        body.extend(destination.port().to_be_bytes());
+                        AF_INET6
+                    }
+                    _ => AF_UNSPEC,
+                };
+                for tlv in &self.tlvs {
+                    body.push(tlv.kind);
+                    body.extend((tlv.value.len() as u16).to_be_bytes());
+                    body.extend(&tlv.value);
+                }
+
+                // Without addresses, there's nothing to say about the
+                // connection.
+                let (command, protocol) = match family {
+                    AF_UNSPEC => (V2_LOCAL, 0x0),
+                    _ => (V2_PROXY, 0x1),
+                };
+                let mut header = V2_SIGNATURE.to_vec();
+                header.extend([command, family << 4 | protocol]);
+                header.extend((body.len() as u16).to_be_bytes());
+                header.extend(body);
+                header
+            }
+        }
+    }
+
+    /// The version of the PROXY protocol that the header was sent with.
+    pub fn version(&self) -> ProxyProto {
+        self.version
+    }
+
+    /// The address of the client that connected to the edge.
+    ///
+    /// This is [None] if the header didn't carry TCP/IP addresses, e.g. for
+    /// v1 `UNKNOWN` or v2 `LOCAL` headers.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.addrs.map(|(source, _)| source)
+    }
+
+    /// The address that the client connected to.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.addrs.map(|(_, destination)| destination)
+    }
+
+    /// The TLVs that followed the addresses in a v2 header.
+    pub fn tlvs(&self) -> &[Tlv] {
+        &self.tlvs
+    }
+
+    /// Get the value of the first TLV of the given type.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(Tlv::value)
+    }
+
+    /// Read a header from the front of the stream, leaving everything after
+    /// it unread.
+    pub(crate) async fn read_from(
+        mut stream: impl AsyncRead + Unpin,
+    ) -> Result<Self, ProxyProtoError> {
+        let mut signature = [0u8; 12];
+        stream.read_exact(&mut signature).await?;
+
+        if &signature == V2_SIGNATURE {
+            let mut fixed = [0u8; 4];
+            stream.read_exact(&mut fixed).await?;
+            let mut body = vec![0u8; u16::from_be_bytes([fixed[2], fixed[3]]) as usize];
+            stream.read_exact(&mut body).await?;
+            return Self::parse_v2(fixed[0], fixed[1], body.into());
+        }
+
+        if !signature.starts_with(V1_PREFIX) {
+            return Err(ProxyProtoError::Missing);
+        }
+        if signature.windows(2).any(|crlf| crlf == b"\r\n") {
+            return Err(ProxyProtoError::InvalidV1);
+        }
+        // The v1 header is only as long as its line, so read it a byte at a
+        // time to avoid consuming any of the data that follows.
+        let mut line = signature.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() == V1_MAX_LEN {
+                return Err(ProxyProtoError::InvalidV1);
+            }
+            line.push(stream.read_u8().await?);
+        }
+        Self::parse_v1(&line[..line.len() - 2])
+    }
+
+    fn parse_v1(line: &[u8]) -> Result<Self, ProxyProtoError> {
+        let line = std::str::from_utf8(line).map_err(|_| ProxyProtoError::InvalidV1)?;
+        let fields = line.split(' ').collect::<Vec<_>>();
+        let addrs = match fields.as_slice() {
+            ["PROXY", "UNKNOWN", ..] => None,
+            ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+            {
+                let ip = |addr: &str| match (*family, addr.parse()) {
+                    ("TCP4", Ok(ip @ IpAddr::V4(_))) | ("TCP6", Ok(ip @ IpAddr::V6(_))) => Ok(ip),
+                    _ => Err(ProxyProtoError::InvalidV1),
+                };
+                let port = |port: &str| port.parse::<u16>().map_err(|_| ProxyProtoError::InvalidV1);
+                Some((
+                    SocketAddr::new(ip(source)?, port(source_port)?),
+                    SocketAddr::new(ip(destination)?, port(destination_port)?),
+                ))
+            }
+            _ => return Err(ProxyProtoError::InvalidV1),
+        };
+        Ok(ProxyProtoHeader {
+            version: ProxyProto::V1,
+            addrs,
+            tlvs: vec![],
+        })
+    }
+
+    fn parse_v2(version_command: u8, family: u8, mut body: Bytes) -> Result<Self, ProxyProtoError> {
+        if version_command != V2_LOCAL && version_command != V2_PROXY {
+            return Err(ProxyProtoError::UnsupportedV2);
+        }
+        let addrs_len = match family >> 4 {
+            AF_UNSPEC => 0,
+            AF_INET => 12,
+            AF_INET6 => 36,
+            AF_UNIX => 216,
+            _ => return Err(ProxyProtoError::UnsupportedV2),
+        };
+        if body.len() < addrs_len {
+            return Err(ProxyProtoError::InvalidAddresses);
+        }
+        let addrs = body.split_to(addrs_len);
+
+        // Addresses are only meaningful for proxied TCP/IP connections, and
+        // must be ignored for local ones.
+        let addrs = match (version_command, family >> 4) {
+            (V2_PROXY, AF_INET) => {
+                let ip =
+                    |at: usize| Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[at..at + 4]).unwrap());
+                let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+                Some((
+                    SocketAddr::new(ip(0).into(), port(8)),
+                    SocketAddr::new(ip(4).into(), port(10)),
+                ))
+            }
+            (V2_PROXY, AF_INET6) => {
+                let ip =
+                    |at: usize| Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[at..at + 16]).unwrap());
+                let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+                Some((
+                    SocketAddr::new(ip(0).into(), port(32)),
+                    SocketAddr::new(ip(16).into(), port(34)),
+                ))
+            }
+            _ => None,
+        };
+
+        let mut tlvs = vec![];
+        while !body.is_empty() {
+            if body.len() < 3 {
+                return Err(ProxyProtoError::InvalidTlvs);
+            }
+            let kind = body[0];
+            let len = u16::from_be_bytes([body[1], body[2]]) as usize;
+            if body.len() < 3 + len {
+                return Err(ProxyProtoError::InvalidTlvs);
+            }
+            let mut tlv = body.split_to(3 + len);
+            tlvs.push(Tlv {
+                kind,
+                value: tlv.split_off(3),
+            });
+        }
+
+        Ok(ProxyProtoHeader {
+            version: ProxyProto::V2,
+            addrs,
+            tlvs,
+        })
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(ip.to_ipv6_mapped().into(), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn read(mut header: &[u8]) -> (Result<ProxyProtoHeader, ProxyProtoError>, &[u8]) {
+        let res = ProxyProtoHeader::read_from(&mut header).await;
+        (res, header)
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (header, rest) = read(b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 443\r\nGET /").await;
+        let header = header.unwrap();
+        assert_eq!(b"GET /", rest);
+        assert_eq!(ProxyProto::V1, header.version());
+        assert_eq!(Some("1.2.3.4:1234".parse().unwrap()), header.source());
+        assert_eq!(Some("5.6.7.8:443".parse().unwrap()), header.destination());
+
+        let (header, _) = read(b"PROXY TCP6 ::1 2001:db8::1 1234 443\r\n").await;
+        assert_eq!(
+            Some("[::1]:1234".parse().unwrap()),
+            header.unwrap().source()
+        );
+
+        let (header, rest) = read(b"PROXY UNKNOWN\r\ndata").await;
+        assert_eq!(None, header.unwrap().source());
+        assert_eq!(b"data", rest);
+
+        for invalid in [
+            &b"PROXY TCP4 ::1 ::1 1234 443\r\n"[..],
+            b"PROXY TCP4 1.2.3.4 5.6.7.8 1234\r\n",
+            b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 99999\r\n",
+            b"PROXY UDP4 1.2.3.4 5.6.7.8 1234 443\r\n",
+            &[b"PROXY ".as_slice(), &[b'A'; 200]].concat(),
+        ] {
+            assert_eq!(Err(ProxyProtoError::InvalidV1), read(invalid).await.0);
+        }
+        assert_eq!(
+            Err(ProxyProtoError::Missing),
+            read(b"GET / HTTP/1.1\r\n").await.0
+        );
+        assert_eq!(
+            Err(ProxyProtoError::Io(io::ErrorKind::UnexpectedEof)),
+            read(b"PROXY TCP4 1.2.3.4").await.0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut raw = V2_SIGNATURE.to_vec();
+        raw.extend([V2_PROXY, 0x11, 0, 12 + 6]);
+        raw.extend([1, 2, 3, 4, 5, 6, 7, 8, 0x04, 0xd2, 0x01, 0xbb]);
+        raw.extend([0x01, 0, 3]);
+        raw.extend(b"h2x");
+        raw.extend(b"data");
+
+        let (header, rest) = read(&raw).await;
+        let header = header.unwrap();
+        assert_eq!(b"data", rest);
+        assert_eq!(ProxyProto::V2, header.version());
+        assert_eq!(Some("1.2.3.4:1234".parse().unwrap()), header.source());
+        assert_eq!(Some("5.6.7.8:443".parse().unwrap()), header.destination());
+        assert_eq!(Some(&b"h2x"[..]), header.tlv(0x01));
+        assert_eq!(1, header.tlvs().len());
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([V2_LOCAL, 0x00, 0, 0]);
+        assert_eq!(None, read(&local).await.0.unwrap().source());
+
+        let mut bad_tlv = V2_SIGNATURE.to_vec();
+        bad_tlv.extend([V2_PROXY, 0x11, 0, 12 + 3]);
+        bad_tlv.extend([0; 12]);
+        bad_tlv.extend([0x01, 0, 5]);
+        assert_eq!(Err(ProxyProtoError::InvalidTlvs), read(&bad_tlv).await.0);
+
+        let mut short = V2_SIGNATURE.to_vec();
+        short.extend([V2_PROXY, 0x21, 0, 12]);
+        short.extend([0; 12]);
+        assert_eq!(Err(ProxyProtoError::InvalidAddresses), read(&short).await.0);
+
+        let mut version = V2_SIGNATURE.to_vec();
+        version.extend([0x31, 0x11, 0, 0]);
+        assert_eq!(Err(ProxyProtoError::UnsupportedV2), read(&version).await.0);
+    }
+
+    #[tokio::test]
+    async fn test_encode() {
+        let source: SocketAddr = "1.2.3.4:1234".parse().unwrap();
+        let destination: SocketAddr = "5.6.7.8:443".parse().unwrap();
+
+        let v1 = ProxyProtoHeader::new(ProxyProto::V1, source, destination);
+        assert_eq!(
+            b"PROXY TCP4 1.2.3.4 5.6.7.8 1234 443\r\n".to_vec(),
+            v1.encode()
+        );
+        assert_eq!(v1, read(&v1.encode()).await.0.unwrap());
+
+        let v2 = ProxyProtoHeader::new(ProxyProto::V2, source, destination);
+        assert_eq!(v2, read(&v2.encode()).await.0.unwrap());
+
+        let mixed = ProxyProtoHeader::new(ProxyProto::V2, source, "[::1]:443".parse().unwrap());
+        let decoded = read(&mixed.encode()).await.0.unwrap();
+        assert_eq!(
+            Some("[::ffff:1.2.3.4]:1234".parse().unwrap()),
+            decoded.source()
+        );
+
+        let v1 = ProxyProtoHeader::new(ProxyProto::V1, "[::1]:1".parse().unwrap(), destination);
+        assert_eq!(
+            b"PROXY TCP6 ::1 ::ffff:5.6.7.8 1 443\r\n".to_vec(),
+            v1.encode()
+        );
+
+        assert!(ProxyProtoHeader::new(ProxyProto::None, source, destination)
+            .encode()
+            .is_empty());
+
+        let unknown = ProxyProtoHeader::unknown(ProxyProto::V1);
+        assert_eq!(b"PROXY UNKNOWN\r\n".to_vec(), unknown.encode());
+        assert_eq!(unknown, read(&unknown.encode()).await.0.unwrap());
+
+        let local = ProxyProtoHeader::unknown(ProxyProto::V2);
+        let mut expected = V2_SIGNATURE.to_vec();
+        expected.extend([V2_LOCAL, 0x00, 0, 0]);
+        assert_eq!(expected, local.encode());
+        assert_eq!(local, read(&local.encode()).await.0.unwrap());
+    }
+}
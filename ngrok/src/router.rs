@@ -0,0 +1,535 @@
+#[cfg(not(target_os = "windows"))]
+use std::path::PathBuf;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+#[cfg(not(target_os = "windows"))]
+use tokio::net::UnixStream;
+use tokio::{
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::{
+    debug,
+    warn,
+};
+
+use crate::{
+    tunnel::ForwardOptions,
+    tunnel_ext::{
+        join_streams,
+        write_proxy_header,
+    },
+    Conn,
+};
+
+// How long to wait for the client to say enough to be sniffed by default.
+const DEFAULT_PEEK_TIMEOUT: Duration = Duration::from_secs(5);
+// The most that will be peeked from a connection, which is enough for a TLS
+// record holding the ClientHello.
+const MAX_PEEK: usize = 5 + (1 << 14);
+
+const TLS_HANDSHAKE: u8 = 0x16;
+const CLIENT_HELLO: u8 = 0x01;
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_ALPN: u16 = 0x0010;
+
+const SSH_BANNER: &[u8] = b"SSH-";
+const HTTP_METHODS: &[&[u8]] = &[
+    b"GET ",
+    b"HEAD ",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"CONNECT ",
+    b"OPTIONS ",
+    b"TRACE ",
+    b"PATCH ",
+    // The HTTP/2 connection preface.
+    b"PRI ",
+];
+
+/// A protocol recognized from the first bytes of a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// A TLS ClientHello.
+    Tls,
+    /// An SSH identification banner.
+    Ssh,
+    /// An HTTP/1.x request line, or the HTTP/2 connection preface.
+    Http,
+}
+
+/// What was learned about a connection from its first bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sniffed {
+    protocol: Option<Protocol>,
+    server_name: Option<String>,
+    alpn_protocols: Vec<Vec<u8>>,
+}
+
+impl Sniffed {
+    /// The protocol that the connection started with, if it was recognized.
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+    }
+
+    /// The server name from the TLS ClientHello's SNI extension.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The protocols offered in the TLS ClientHello's ALPN extension.
+    pub fn alpn_protocols(&self) -> &[Vec<u8>] {
+        &self.alpn_protocols
+    }
+
+    // Sniff the bytes peeked from the front of a connection, or return None
+    // if more are needed to tell.
+    fn from_peeked(peeked: &[u8]) -> Option<Self> {
+        let protocol = match peeked {
+            [] => return None,
+            [TLS_HANDSHAKE, ..] => return Self::from_tls_record(peeked),
+            _ if peeked.starts_with(SSH_BANNER) => Protocol::Ssh,
+            _ if HTTP_METHODS.iter().any(|method| peeked.starts_with(method)) => Protocol::Http,
+            // Could still turn out to be one of them.
+            _ if SSH_BANNER.starts_with(peeked)
+                || HTTP_METHODS.iter().any(|method| method.starts_with(peeked)) =>
+            {
+                return None
+            }
+            _ => return Some(Default::default()),
+        };
+        Some(Sniffed {
+            protocol: Some(protocol),
+            ..Default::default()
+        })
+    }
+
+    fn from_tls_record(peeked: &[u8]) -> Option<Self> {
+        let record_len = match peeked.get(3..5) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => return None,
+        };
+        let record = peeked.get(5..5 + record_len).or_else(|| {
+            // Take what there is if the record is too big to wait for.
+            (peeked.len() >= MAX_PEEK).then(|| &peeked[5..])
+        })?;
+        let mut sniffed = Sniffed {
+            protocol: Some(Protocol::Tls),
+            ..Default::default()
+        };
+        // A malformed or truncated ClientHello is still TLS, just without the
+        // extensions.
+        let _ = sniffed.parse_client_hello(record);
+        Some(sniffed)
+    }
+
+    fn parse_client_hello(&mut self, record: &[u8]) -> Option<()> {
+        let mut hello = Reader(record);
+        if hello.u8()? != CLIENT_HELLO {
+            return None;
+        }
+        hello.take(3)?; // length
+        hello.take(2 + 32)?; // version and random
+        hello.vec8()?; // session ID
+        hello.vec16()?; // cipher suites
+        hello.vec8()?; // compression methods
+        let mut extensions = Reader(hello.vec16()?);
+        while !extensions.0.is_empty() {
+            let kind = extensions.u16()?;
+            let mut data = Reader(extensions.vec16()?);
+            match kind {
+                EXT_SERVER_NAME => {
+                    let mut names = Reader(data.vec16()?);
+                    while !names.0.is_empty() {
+                        let name_type = names.u8()?;
+                        let name = names.vec16()?;
+                        // Only host names are defined.
+                        if name_type == 0 {
+                            self.server_name = std::str::from_utf8(name).ok().map(String::from);
+                        }
+                    }
+                }
+                EXT_ALPN => {
+                    let mut protocols = Reader(data.vec16()?);
+                    while !protocols.0.is_empty() {
+                        self.alpn_protocols.push(protocols.vec8()?.to_vec());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(())
+    }
+}
+
+// Reads big-endian, length-prefixed TLS structures.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+/// Peek at the first bytes of a connection to find out what protocol it's
+/// speaking, and for TLS, the SNI and ALPN from its ClientHello.
+///
+/// The peeked bytes are still read from the connection afterwards. If the
+/// client doesn't say enough within `peek_timeout`, or the connection ends,
+/// nothing is recognized.
+///
+/// If the tunnel terminates TLS at the agent, the server name and ALPN
+/// protocol negotiated there are used instead, and the protocol is sniffed
+/// from the decrypted stream.
+pub async fn sniff(conn: &mut Conn, peek_timeout: Duration) -> Sniffed {
+    let peek = async {
+        loop {
+            if let Some(sniffed) = Sniffed::from_peeked(conn.peeked()) {
+                return sniffed;
+            }
+            if conn.peeked().len() >= MAX_PEEK || !matches!(conn.peek_more().await, Ok(1..)) {
+                return Default::default();
+            }
+        }
+    };
+    let mut sniffed = timeout(peek_timeout, peek).await.unwrap_or_default();
+    if let Some(tls) = conn.tls_info() {
+        sniffed.server_name = tls.server_name().map(String::from);
+        sniffed.alpn_protocols = tls.alpn_protocol().map(Vec::from).into_iter().collect();
+    }
+    sniffed
+}
+
+type Handler = Arc<dyn Fn(Conn) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Where a [Router] sends a connection.
+#[derive(Clone)]
+pub enum Route {
+    /// Forward it to a local TCP address.
+    Tcp(String),
+    /// Forward it to a local Unix socket.
+    #[cfg(not(target_os = "windows"))]
+    Unix(PathBuf),
+    /// Hand it to an async function, which is spawned as its own task.
+    Handler(Handler),
+}
+
+impl Route {
+    /// Forward connections to the given local TCP address.
+    pub fn tcp(addr: impl Into<String>) -> Self {
+        Route::Tcp(addr.into())
+    }
+
+    /// Forward connections to the given local Unix socket.
+    #[cfg(not(target_os = "windows"))]
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Route::Unix(path.into())
+    }
+
+    /// Hand connections to the given async function.
+    pub fn handler<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Conn) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Route::Handler(Arc::new(move |conn| Box::pin(handler(conn))))
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Route::Tcp(addr) => f.debug_tuple("Tcp").field(addr).finish(),
+            #[cfg(not(target_os = "windows"))]
+            Route::Unix(path) => f.debug_tuple("Unix").field(path).finish(),
+            Route::Handler(_) => f.write_str("Handler"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Rule {
+    ServerName(String),
+    Alpn(Vec<u8>),
+    Protocol(Protocol),
+}
+
+impl Rule {
+    fn matches(&self, sniffed: &Sniffed) -> bool {
+        match self {
+            Rule::ServerName(pattern) => sniffed
+                .server_name()
+                .is_some_and(|name| server_name_matches(pattern, name)),
+            Rule::Alpn(protocol) => sniffed.alpn_protocols().contains(protocol),
+            Rule::Protocol(protocol) => sniffed.protocol() == Some(*protocol),
+        }
+    }
+}
+
+// Compare server names case-insensitively, with a leading `*.` matching any
+// one or more labels.
+fn server_name_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some(suffix) if suffix.starts_with('.') => {
+            name.len() > suffix.len()
+                && name
+                    .get(name.len() - suffix.len()..)
+                    .is_some_and(|end| end.eq_ignore_ascii_case(suffix))
+        }
+        _ => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+/// Dispatches tunnel connections to different backends by sniffing their
+/// first bytes, so that one tunnel can front many services.
+///
+/// Rules are tried in the order they were added, and the first match wins.
+/// Connections that don't match any rule go to the fallback route, or are
+/// closed if there isn't one. Serve a tunnel with a router using
+/// [TunnelExt::forward_routed](crate::prelude::TunnelExt::forward_routed).
+#[derive(Clone, Debug)]
+pub struct Router {
+    rules: Vec<(Rule, Route)>,
+    fallback: Option<Route>,
+    peek_timeout: Duration,
+    forward_options: ForwardOptions,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            rules: vec![],
+            fallback: None,
+            peek_timeout: DEFAULT_PEEK_TIMEOUT,
+            forward_options: Default::default(),
+        }
+    }
+}
+
+impl Router {
+    /// Create a router without any rules.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Route TLS connections whose SNI matches the server name. A leading
+    /// `*.` matches any subdomain.
+    pub fn server_name(mut self, server_name: impl Into<String>, route: Route) -> Self {
+        self.rules
+            .push((Rule::ServerName(server_name.into()), route));
+        self
+    }
+
+    /// Route TLS connections that offer the protocol via ALPN.
+    pub fn alpn(mut self, protocol: impl Into<Vec<u8>>, route: Route) -> Self {
+        self.rules.push((Rule::Alpn(protocol.into()), route));
+        self
+    }
+
+    /// Route connections that start with the given protocol.
+    pub fn protocol(mut self, protocol: Protocol, route: Route) -> Self {
+        self.rules.push((Rule::Protocol(protocol), route));
+        self
+    }
+
+    /// Route connections that don't match any rule.
+    ///
+    /// This includes connections that don't send anything within the peek
+    /// timeout, like those for protocols where the server speaks first.
+    pub fn fallback(mut self, route: Route) -> Self {
+        self.fallback = Some(route);
+        self
+    }
+
+    /// How long to wait for each connection's first bytes. Defaults to 5
+    /// seconds.
+    pub fn peek_timeout(mut self, peek_timeout: Duration) -> Self {
+        self.peek_timeout = peek_timeout;
+        self
+    }
+
+    /// Options for forwarding to [Route::Tcp] and [Route::Unix] backends.
+    pub fn forward_options(mut self, options: ForwardOptions) -> Self {
+        self.forward_options = options;
+        self
+    }
+
+    /// Find the route for a connection that was sniffed.
+    pub fn route(&self, sniffed: &Sniffed) -> Option<&Route> {
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(sniffed))
+            .map(|(_, route)| route)
+            .or(self.fallback.as_ref())
+    }
+
+    /// Sniff the connection and send it to its route, replaying the bytes that
+    /// were peeked.
+    ///
+    /// Resolves once the connection has been handed off to its backend.
+    pub async fn dispatch(&self, mut conn: Conn) -> Result<(), io::Error> {
+        let sniffed = sniff(&mut conn, self.peek_timeout).await;
+        let route = match self.route(&sniffed) {
+            Some(route) => route,
+            None => {
+                debug!(?sniffed, "no route for tunnel connection, closing it");
+                return Ok(());
+            }
+        };
+        debug!(?sniffed, ?route, "routing tunnel connection");
+        match route {
+            Route::Tcp(addr) => {
+                let mut local = TcpStream::connect(addr.as_str()).await?;
+                write_proxy_header(&mut local, &conn, &self.forward_options).await?;
+                join_streams(conn, local);
+            }
+            #[cfg(not(target_os = "windows"))]
+            Route::Unix(path) => {
+                let mut local = UnixStream::connect(path).await?;
+                write_proxy_header(&mut local, &conn, &self.forward_options).await?;
+                join_streams(conn, local);
+            }
+            Route::Handler(handler) => {
+                tokio::spawn(handler(conn));
+            }
+        }
+        Ok(())
+    }
+
+    // Dispatch the connection in the background, logging failures.
+    pub(crate) fn spawn_dispatch(self: &Arc<Self>, conn: Conn) {
+        let router = self.clone();
+        tokio::spawn(async move {
+            if let Err(error) = router.dispatch(conn).await {
+                warn!(%error, "error establishing routed local connection");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A ClientHello for the server name, offering h2 and http/1.1.
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let mut sni = vec![0];
+        sni.extend((server_name.len() as u16).to_be_bytes());
+        sni.extend(server_name.as_bytes());
+        let mut sni_ext = (sni.len() as u16).to_be_bytes().to_vec();
+        sni_ext.extend(sni);
+
+        let mut alpn = vec![];
+        for protocol in [&b"h2"[..], b"http/1.1"] {
+            alpn.push(protocol.len() as u8);
+            alpn.extend(protocol);
+        }
+        let mut alpn_ext = (alpn.len() as u16).to_be_bytes().to_vec();
+        alpn_ext.extend(alpn);
+
+        let mut extensions = vec![];
+        for (kind, data) in [(EXT_SERVER_NAME, sni_ext), (EXT_ALPN, alpn_ext)] {
+            extensions.extend(kind.to_be_bytes());
+            extensions.extend((data.len() as u16).to_be_bytes());
+            extensions.extend(data);
+        }
+
+        let mut body = vec![0x03, 0x03];
+        body.extend([0; 32]);
+        body.extend([0]); // session ID
+        body.extend([0, 2, 0x13, 0x01]); // cipher suites
+        body.extend([1, 0]); // compression methods
+        body.extend((extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+
+        let mut handshake = vec![CLIENT_HELLO];
+        handshake.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(body);
+
+        let mut record = vec![TLS_HANDSHAKE, 0x03, 0x01];
+        record.extend((handshake.len() as u16).to_be_bytes());
+        record.extend(handshake);
+        record
+    }
+
+    #[test]
+    fn test_sniff() {
+        let hello = client_hello("api.example.com");
+        let sniffed = Sniffed::from_peeked(&hello).unwrap();
+        assert_eq!(Some(Protocol::Tls), sniffed.protocol());
+        assert_eq!(Some("api.example.com"), sniffed.server_name());
+        assert_eq!(
+            [b"h2".to_vec(), b"http/1.1".to_vec()].as_slice(),
+            sniffed.alpn_protocols()
+        );
+        assert_eq!(None, Sniffed::from_peeked(&hello[..hello.len() - 1]));
+        assert_eq!(None, Sniffed::from_peeked(&hello[..3]));
+
+        let ssh = Sniffed::from_peeked(b"SSH-2.0-OpenSSH_9.0\r\n").unwrap();
+        assert_eq!(Some(Protocol::Ssh), ssh.protocol());
+        assert_eq!(None, Sniffed::from_peeked(b"SS"));
+
+        let http = Sniffed::from_peeked(b"GET / HTTP/1.1\r\n").unwrap();
+        assert_eq!(Some(Protocol::Http), http.protocol());
+        assert_eq!(None, Sniffed::from_peeked(b"OPTIO"));
+
+        assert_eq!(
+            Some(Sniffed::default()),
+            Sniffed::from_peeked(b"\x00\x01binary")
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let router = Router::new()
+            .server_name("*.internal.example.com", Route::tcp("internal"))
+            .server_name("api.example.com", Route::tcp("api"))
+            .alpn("h2", Route::tcp("h2"))
+            .protocol(Protocol::Ssh, Route::tcp("ssh"))
+            .fallback(Route::tcp("fallback"));
+
+        let route = |peeked: &[u8]| match router.route(&Sniffed::from_peeked(peeked).unwrap()) {
+            Some(Route::Tcp(addr)) => addr.clone(),
+            other => panic!("unexpected route {other:?}"),
+        };
+
+        assert_eq!("api", route(&client_hello("API.example.com")));
+        assert_eq!("internal", route(&client_hello("db.internal.example.com")));
+        assert_eq!("h2", route(&client_hello("internal.example.com")));
+        assert_eq!("ssh", route(b"SSH-2.0-OpenSSH_9.0\r\n"));
+        assert_eq!("fallback", route(b"GET / HTTP/1.1\r\n"));
+
+        assert!(Router::new().route(&Sniffed::default()).is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    env,
+    io,
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use futures::Future;
+
+/// A source of authtokens for an ngrok [Session](crate::Session).
+///
+/// The provider is called before every authentication attempt, including
+/// those made when the session reconnects. If the server rejects the token, it
+/// is called once more before the error is surfaced.
+///
+/// It is implemented for all `Fn() -> impl Future<Output = Result<String,
+/// io::Error>>` closures.
+#[async_trait]
+pub trait AuthtokenProvider: Send + Sync + 'static {
+    /// Get the authtoken to authenticate with.
+    async fn authtoken(&self) -> Result<String, io::Error>;
+}
+
+#[async_trait]
+impl<F, Fut> AuthtokenProvider for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, io::Error>> + Send,
+{
+    async fn authtoken(&self) -> Result<String, io::Error> {
+        self().await
+    }
+}
+
+/// Read the authtoken from a file, ignoring surrounding whitespace.
+///
+/// The file is re-read every time the token is needed.
+#[derive(Debug, Clone)]
+pub struct AuthtokenFile {
+    path: PathBuf,
+}
+
+impl AuthtokenFile {
+    /// Read the authtoken from the file at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AuthtokenFile { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl AuthtokenProvider for AuthtokenFile {
+    async fn authtoken(&self) -> Result<String, io::Error> {
+        let token = tokio::fs::read_to_string(&self.path).await?;
+        Ok(token.trim().into())
+    }
+}
+
+/// Read the authtoken from an ngrok agent config file.
+///
+/// Both the version 2 format, with a top-level `authtoken` key, and the version
+/// 3 format, with an `agent.authtoken` key, are supported. The file is re-read
+/// every time the token is needed.
+#[derive(Debug, Clone)]
+pub struct AgentConfigAuthtoken {
+    path: PathBuf,
+}
+
+impl AgentConfigAuthtoken {
+    /// Read the authtoken from the agent config file at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AgentConfigAuthtoken { path: path.into() }
+    }
+
+    /// Read the authtoken from the agent config file in its default location
+    /// for this platform.
+    pub fn default_location() -> Result<Self, io::Error> {
+        default_agent_config_path().map(Self::new).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "couldn't determine the agent config location",
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl AuthtokenProvider for AgentConfigAuthtoken {
+    async fn authtoken(&self) -> Result<String, io::Error> {
+        let contents = tokio::fs::read_to_string(&self.path).await?;
+        parse_agent_config_authtoken(&contents)
+    }
+}
+
+fn parse_agent_config_authtoken(contents: &str) -> Result<String, io::Error> {
+    let config: serde_yaml::Value = serde_yaml::from_str(contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    config
+        .get("authtoken")
+        .or_else(|| config.get("agent").and_then(|agent| agent.get("authtoken")))
+        .and_then(serde_yaml::Value::as_str)
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no authtoken in the agent config file",
+            )
+        })
+}
+
+/// The default location of the ngrok agent config file on this platform.
+///
+/// Prefers the legacy `~/.ngrok2/ngrok.yml` if it exists, like the agent does.
+pub(crate) fn default_agent_config_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+
+    if let Some(legacy) = home.as_ref().map(|home| home.join(".ngrok2/ngrok.yml")) {
+        if legacy.exists() {
+            return Some(legacy);
+        }
+    }
+
+    let config_dir = match env::consts::OS {
+        "windows" => env::var_os("LOCALAPPDATA").map(PathBuf::from),
+        "macos" => home.map(|home| home.join("Library/Application Support")),
+        _ => env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".config"))),
+    };
+    config_dir.map(|dir| dir.join("ngrok").join("ngrok.yml"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_agent_config_authtoken() {
+        assert_eq!(
+            "v2token",
+            parse_agent_config_authtoken("version: \"2\"\nauthtoken: v2token\nregion: us\n")
+                .unwrap()
+        );
+        assert_eq!(
+            "v3token",
+            parse_agent_config_authtoken("version: 3\nagent:\n  authtoken: v3token\n").unwrap()
+        );
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            parse_agent_config_authtoken("version: \"2\"\n")
+                .unwrap_err()
+                .kind()
+        );
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            parse_agent_config_authtoken("authtoken: [")
+                .unwrap_err()
+                .kind()
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+    task::{
+        ready,
+        Context,
+        Poll,
+    },
+};
+
+use async_trait::async_trait;
+use futures::{
+    future,
+    stream::{
+        FuturesUnordered,
+        SelectAll,
+    },
+    Stream,
+    StreamExt,
+};
+#[cfg(feature = "hyper")]
+use hyper::server::accept::Accept;
+use tracing::debug;
+
+#[cfg(feature = "hyper")]
+use crate::tunnel::poll_accept_conn;
+use crate::{
+    config::{
+        HttpTunnelBuilder,
+        LabeledTunnelBuilder,
+        TcpTunnelBuilder,
+        TlsTunnelBuilder,
+        TunnelBuilder,
+    },
+    session::{
+        ConnectError,
+        RpcError,
+        SessionBuilder,
+    },
+    tunnel::{
+        AcceptError,
+        LabeledTunnel,
+        LabelsTunnel,
+    },
+    Conn,
+    Session,
+    Tunnel,
+};
+
+const DEFAULT_POOL_SIZE: usize = 2;
+
+/// How a [SessionPool] picks the session for a new tunnel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Cycle through the sessions in order.
+    #[default]
+    RoundRobin,
+    /// Pick the session with the fewest bound tunnels, breaking ties in
+    /// round-robin order.
+    LeastLoaded,
+}
+
+/// A builder for a [SessionPool].
+#[derive(Clone)]
+pub struct SessionPoolBuilder {
+    builder: SessionBuilder,
+    size: usize,
+    placement: Placement,
+}
+
+impl SessionPoolBuilder {
+    /// The number of sessions to open. Defaults to 2.
+    ///
+    /// A pool always has at least one session.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// How to pick the session for each new tunnel. Defaults to
+    /// [Placement::RoundRobin].
+    pub fn placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Open all of the pool's sessions concurrently.
+    ///
+    /// Fails with the first error if any of them can't be established.
+    pub async fn connect(&self) -> Result<SessionPool, ConnectError> {
+        let sessions = future::try_join_all((0..self.size).map(|_| self.builder.connect())).await?;
+        Ok(SessionPool {
+            inner: Arc::new(PoolInner {
+                sessions,
+                placement: self.placement,
+                next: AtomicUsize::new(0),
+            }),
+        })
+    }
+}
+
+/// A pool of ngrok sessions opened from the same [SessionBuilder].
+///
+/// Spreads tunnels across several connections to the ngrok service rather than
+/// carrying all of them over one. Each session reconnects independently.
+#[derive(Clone)]
+pub struct SessionPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    sessions: Vec<Session>,
+    placement: Placement,
+    next: AtomicUsize,
+}
+
+impl SessionPool {
+    /// Create a new [SessionPoolBuilder] whose sessions are configured by the
+    /// given [SessionBuilder].
+    pub fn builder(builder: SessionBuilder) -> SessionPoolBuilder {
+        SessionPoolBuilder {
+            builder,
+            size: DEFAULT_POOL_SIZE,
+            placement: Default::default(),
+        }
+    }
+
+    /// All of the sessions in this pool.
+    pub fn sessions(&self) -> &[Session] {
+        &self.inner.sessions
+    }
+
+    /// Pick the session to start the next tunnel in, according to the pool's
+    /// [Placement].
+    pub async fn next_session(&self) -> Session {
+        self.pick(1).await.remove(0)
+    }
+
+    /// Start building a tunnel backing an HTTP endpoint, in the session picked
+    /// by the pool's [Placement].
+    pub async fn http_endpoint(&self) -> HttpTunnelBuilder {
+        self.next_session().await.http_endpoint()
+    }
+
+    /// Start building a tunnel backing a TCP endpoint, in the session picked
+    /// by the pool's [Placement].
+    pub async fn tcp_endpoint(&self) -> TcpTunnelBuilder {
+        self.next_session().await.tcp_endpoint()
+    }
+
+    /// Start building a tunnel backing a TLS endpoint, in the session picked
+    /// by the pool's [Placement].
+    pub async fn tls_endpoint(&self) -> TlsTunnelBuilder {
+        self.next_session().await.tls_endpoint()
+    }
+
+    /// Start building a labeled tunnel, in the session picked by the pool's
+    /// [Placement].
+    pub async fn labeled_tunnel(&self) -> LabeledTunnelBuilder {
+        self.next_session().await.labeled_tunnel()
+    }
+
+    /// Start the same labeled tunnel in several of the pool's sessions.
+    ///
+    /// Connections from all of them are merged into a single [PooledTunnel].
+    /// The number of copies is capped at the size of the pool. If any copy
+    /// fails to start, the others are closed and the first error is returned.
+    pub async fn listen_redundant(
+        &self,
+        tunnel: LabeledTunnelBuilder,
+        copies: usize,
+    ) -> Result<PooledTunnel, RpcError> {
+        let sessions = self.pick(copies.clamp(1, self.inner.sessions.len())).await;
+        let results = future::join_all(sessions.into_iter().map(|session| {
+            let builder = tunnel.clone().for_session(session);
+            async move { builder.listen().await }
+        }))
+        .await;
+
+        let mut tunnels = Vec::with_capacity(results.len());
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(tunnel) => tunnels.push(tunnel),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(error) = error {
+            for mut tunnel in tunnels {
+                let _ = tunnel.close().await;
+            }
+            return Err(error);
+        }
+
+        // The copies were all started from the same builder.
+        let first = &tunnels[0];
+        Ok(PooledTunnel {
+            forwards_to: first.forwards_to().into(),
+            metadata: first.metadata().into(),
+            labels: first.labels().clone(),
+            tunnels: futures::stream::select_all(tunnels),
+            error: None,
+        })
+    }
+
+    /// Close every session in the pool.
+    ///
+    /// Returns the first error encountered, but always closes all of them.
+    pub async fn close(&self) -> Result<(), RpcError> {
+        future::join_all(self.inner.sessions.iter().map(|session| session.close()))
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    async fn pick(&self, count: usize) -> Vec<Session> {
+        let sessions = &self.inner.sessions;
+        let start = self.inner.next.fetch_add(count, Ordering::Relaxed);
+        let mut order = (0..sessions.len())
+            .map(|i| (start + i) % sessions.len())
+            .collect::<Vec<_>>();
+
+        if self.inner.placement == Placement::LeastLoaded {
+            let counts = future::join_all(sessions.iter().map(Session::tunnel_count)).await;
+            order.sort_by_key(|&i| counts[i]);
+        }
+
+        order
+            .into_iter()
+            .take(count)
+            .map(|i| sessions[i].clone())
+            .collect()
+    }
+}
+
+/// A labeled tunnel bound in several sessions of a [SessionPool].
+///
+/// Acts as a single [Tunnel]. Errors from individual copies are only surfaced
+/// once every copy has ended.
+pub struct PooledTunnel {
+    tunnels: SelectAll<LabeledTunnel>,
+    error: Option<AcceptError>,
+    forwards_to: String,
+    metadata: String,
+    labels: HashMap<String, String>,
+}
+
+impl PooledTunnel {
+    /// The IDs of the copies of this tunnel that are still open.
+    pub fn ids(&self) -> Vec<String> {
+        self.tunnels.iter().map(|tunnel| tunnel.id()).collect()
+    }
+}
+
+#[async_trait]
+impl Tunnel for PooledTunnel {
+    /// The ID of the first copy of this tunnel that's still open, or an empty
+    /// string if none are. See [PooledTunnel::ids] for all of them.
+    fn id(&self) -> String {
+        self.tunnels
+            .iter()
+            .next()
+            .map(|tunnel| tunnel.id())
+            .unwrap_or_default()
+    }
+
+    fn forwards_to(&self) -> &str {
+        &self.forwards_to
+    }
+
+    fn metadata(&self) -> &str {
+        &self.metadata
+    }
+
+    /// Close every copy of this tunnel.
+    ///
+    /// Returns the first error encountered, but always closes all of them.
+    async fn close(&mut self) -> Result<(), RpcError> {
+        future::join_all(self.tunnels.iter_mut().map(|tunnel| tunnel.close()))
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Wait for any copy of this tunnel to be rebound.
+    async fn rebound(&mut self) -> Option<String> {
+        let mut rebounds = self
+            .tunnels
+            .iter_mut()
+            .map(|tunnel| tunnel.rebound())
+            .collect::<FuturesUnordered<_>>();
+        while let Some(rebound) = rebounds.next().await {
+            if rebound.is_some() {
+                return rebound;
+            }
+        }
+        None
+    }
+}
+
+impl LabelsTunnel for PooledTunnel {
+    fn labels(&self) -> &HashMap<String, String> {
+        &self.labels
+    }
+}
+
+impl Stream for PooledTunnel {
+    type Item = Result<Conn, AcceptError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(self.tunnels.poll_next_unpin(cx)) {
+                Some(Ok(conn)) => return Poll::Ready(Some(Ok(conn))),
+                // Only that connection failed, not the copy of the tunnel.
+                Some(Err(error)) if error.is_per_connection() => {
+                    return Poll::Ready(Some(Err(error)))
+                }
+                Some(Err(error)) => {
+                    debug!(%error, "pooled tunnel copy failed");
+                    self.error = Some(error);
+                }
+                None => return Poll::Ready(self.error.take().map(Err)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl Accept for PooledTunnel {
+    type Conn = Conn;
+    type Error = AcceptError;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        poll_accept_conn(self, cx)
+    }
+}
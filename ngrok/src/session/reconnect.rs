@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use rand::Rng;
+use thiserror::Error;
+
+use crate::{
+    session::{
+        ConnectError,
+        RpcError,
+    },
+    tunnel::AcceptError,
+};
+
+/// The error that triggered a reconnect attempt.
+#[derive(Error, Debug)]
+pub enum ReconnectError {
+    /// The connection to the ngrok server was lost.
+    #[error("session transport error")]
+    Transport(#[source] AcceptError),
+    /// A new connection to the ngrok server couldn't be established.
+    #[error("failed to reconnect to the ngrok server")]
+    Connect(#[source] ConnectError),
+    /// An existing tunnel couldn't be bound on the new connection, for a
+    /// reason that might go away on another attempt.
+    ///
+    /// Tunnels that can't be rebound for good, e.g. because of an account
+    /// limit, are closed on their own with [AcceptError::Rebind] instead.
+    #[error("failed to rebind tunnel")]
+    Rebind(#[source] RpcError),
+}
+
+impl ReconnectError {
+    /// Whether another reconnect attempt might succeed.
+    ///
+    /// The session gives up as soon as it sees an error that isn't, regardless
+    /// of its [ReconnectPolicy].
+    ///
+    /// Hitting the account's session limit is retried, since the server may
+    /// not have noticed that the old session is gone yet.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ReconnectError::Transport(_) => true,
+            ReconnectError::Connect(ConnectError::Auth(error)) if error.is_session_limit() => true,
+            ReconnectError::Connect(error) => error.is_retryable(),
+            ReconnectError::Rebind(error) => error.is_retryable(),
+        }
+    }
+}
+
+/// A policy governing how an ngrok [Session](crate::Session) reconnects after
+/// losing its connection to the ngrok server.
+///
+/// It is implemented for all `Fn(usize, &ReconnectError) -> Option<Duration>`
+/// closures.
+pub trait ReconnectPolicy: Send + Sync {
+    /// Get the delay before the given reconnect attempt, or [None] to stop
+    /// reconnecting.
+    ///
+    /// Attempts are numbered starting at 1, and the error is the one that
+    /// caused the previous connection or reconnect attempt to fail.
+    fn next_delay(&self, attempt: usize, error: &ReconnectError) -> Option<Duration>;
+}
+
+impl<F> ReconnectPolicy for F
+where
+    F: Fn(usize, &ReconnectError) -> Option<Duration> + Send + Sync,
+{
+    fn next_delay(&self, attempt: usize, error: &ReconnectError) -> Option<Duration> {
+        self(attempt, error)
+    }
+}
+
+/// Reconnect with exponentially increasing delays.
+///
+/// The delay starts at the initial delay and doubles with each attempt up to
+/// the maximum delay. Some amount of random jitter is subtracted from each
+/// delay so that many agents disconnected at the same time don't all reconnect
+/// in lockstep.
+#[derive(Debug, Clone)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    max_attempts: Option<usize>,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        ExponentialBackoff::new(Duration::from_millis(50), Duration::from_secs(30))
+    }
+}
+
+impl ExponentialBackoff {
+    /// Create a new exponential backoff policy with the given initial and
+    /// maximum delays.
+    ///
+    /// Defaults to a jitter of 0.5 and unlimited attempts.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        ExponentialBackoff {
+            initial,
+            max,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+
+    /// The fraction of each delay that may be randomly subtracted from it.
+    ///
+    /// Clamped to the range `0.0..=1.0`. Zero disables jitter.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Stop reconnecting after this many attempts.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+}
+
+impl ReconnectPolicy for ExponentialBackoff {
+    fn next_delay(&self, attempt: usize, _error: &ReconnectError) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let exp = attempt.saturating_sub(1).min(u32::MAX as usize) as u32;
+        let delay = self
+            .initial
+            .checked_mul(2u32.saturating_pow(exp))
+            .unwrap_or(self.max)
+            .min(self.max);
+        if self.jitter == 0.0 {
+            return Some(delay);
+        }
+        Some(delay.mul_f64(1.0 - self.jitter * rand::thread_rng().gen::<f64>()))
+    }
+}
+
+/// Reconnect with a fixed delay between attempts.
+#[derive(Debug, Clone)]
+pub struct FixedInterval {
+    interval: Duration,
+    max_attempts: Option<usize>,
+}
+
+impl FixedInterval {
+    /// Create a new fixed interval policy with unlimited attempts.
+    pub fn new(interval: Duration) -> Self {
+        FixedInterval {
+            interval,
+            max_attempts: None,
+        }
+    }
+
+    /// Stop reconnecting after this many attempts.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+}
+
+impl ReconnectPolicy for FixedInterval {
+    fn next_delay(&self, attempt: usize, _error: &ReconnectError) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        Some(self.interval)
+    }
+}
+
+/// Never reconnect.
+///
+/// The session's tunnels will be closed as soon as its connection to the ngrok
+/// server is lost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NeverReconnect;
+
+impl ReconnectPolicy for NeverReconnect {
+    fn next_delay(&self, _attempt: usize, _error: &ReconnectError) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error() -> ReconnectError {
+        ReconnectError::Transport(AcceptError::Transport(muxado::Error::SessionClosed))
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(1))
+            .jitter(0.0)
+            .max_attempts(6);
+
+        let delays = (1..=7)
+            .map(|attempt| policy.next_delay(attempt, &error()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(800)),
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(1)),
+                None,
+            ],
+            delays
+        );
+
+        // Shouldn't overflow for absurd attempt counts.
+        assert_eq!(
+            Some(Duration::from_secs(1)),
+            policy
+                .jitter(0.0)
+                .max_attempts(usize::MAX)
+                .next_delay(1000, &error())
+        );
+    }
+
+    #[test]
+    fn test_exponential_backoff_jitter() {
+        let policy = ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(1));
+
+        for attempt in 1..100 {
+            let delay = policy.next_delay(attempt, &error()).unwrap();
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_fixed_interval() {
+        let policy = FixedInterval::new(Duration::from_secs(3)).max_attempts(2);
+
+        assert_eq!(Some(Duration::from_secs(3)), policy.next_delay(1, &error()));
+        assert_eq!(Some(Duration::from_secs(3)), policy.next_delay(2, &error()));
+        assert_eq!(None, policy.next_delay(3, &error()));
+    }
+
+    #[test]
+    fn test_never_reconnect() {
+        assert_eq!(None, NeverReconnect.next_delay(1, &error()));
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use async_rustls::rustls::{
+    self,
+    client::{
+        ServerCertVerified,
+        ServerCertVerifier,
+        WebPkiVerifier,
+    },
+    Certificate,
+    ServerName,
+};
+use ring::digest::{
+    digest,
+    SHA256,
+};
+use rustls_pemfile::Item;
+use tokio::sync::OnceCell;
+
+const CERT_BYTES: &[u8] = include_bytes!("../../assets/ngrok.ca.crt");
+
+// The platform's root certificates. Loading them is slow and blocking, so
+// it's only done once.
+static NATIVE_ROOTS: OnceCell<Vec<Vec<u8>>> = OnceCell::const_new();
+
+async fn native_roots() -> Result<&'static [Vec<u8>], io::Error> {
+    NATIVE_ROOTS
+        .get_or_try_init(|| async {
+            tokio::task::spawn_blocking(|| {
+                Ok(rustls_native_certs::load_native_certs()?
+                    .into_iter()
+                    .map(|cert| cert.0)
+                    .collect())
+            })
+            .await
+            .map_err(io::Error::other)?
+        })
+        .await
+        .map(Vec::as_slice)
+}
+
+/// Where to get PEM-encoded TLS material from.
+#[derive(Clone, Debug)]
+pub(crate) enum PemSource {
+    Bytes(Arc<[u8]>),
+    /// Read every time the TLS config is built.
+    File(PathBuf),
+}
+
+impl PemSource {
+    async fn read(&self) -> Result<Vec<Item>, io::Error> {
+        let bytes = match self {
+            PemSource::Bytes(bytes) => bytes.to_vec(),
+            PemSource::File(path) => tokio::fs::read(path).await.map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+            })?,
+        };
+        rustls_pemfile::read_all(&mut io::Cursor::new(bytes))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            PemSource::Bytes(_) => "pem bytes".into(),
+            PemSource::File(path) => path.display().to_string(),
+        }
+    }
+
+    async fn certificates(&self) -> Result<Vec<Vec<u8>>, io::Error> {
+        let certs = self
+            .read()
+            .await?
+            .into_iter()
+            .filter_map(|it| match it {
+                Item::X509Certificate(bs) => Some(bs),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no certificates found in {}", self.describe()),
+            ));
+        }
+        Ok(certs)
+    }
+
+    async fn private_key(&self) -> Result<Vec<u8>, io::Error> {
+        self.read()
+            .await?
+            .into_iter()
+            .find_map(|it| match it {
+                Item::RSAKey(bs) | Item::PKCS8Key(bs) | Item::ECKey(bs) => Some(bs),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no private key found in {}", self.describe()),
+                )
+            })
+    }
+}
+
+/// A certificate chain and private key to authenticate to the ngrok server
+/// with.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientCert {
+    pub(crate) chain: PemSource,
+    pub(crate) key: PemSource,
+}
+
+/// A SHA-256 pin for a certificate presented by the ngrok server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CertPin {
+    /// The hash of the whole DER-encoded certificate.
+    Certificate([u8; 32]),
+    /// The hash of the certificate's DER-encoded SubjectPublicKeyInfo.
+    PublicKey([u8; 32]),
+}
+
+impl CertPin {
+    fn matches(&self, cert: &[u8]) -> bool {
+        match self {
+            CertPin::Certificate(hash) => digest(&SHA256, cert).as_ref() == hash,
+            CertPin::PublicKey(hash) => subject_public_key_info(cert)
+                .map(|spki| digest(&SHA256, spki).as_ref() == hash)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// The settings used to build the TLS config for the connection to the ngrok
+/// server.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TlsOptions {
+    pub(crate) root_cas: Vec<PemSource>,
+    pub(crate) native_roots: bool,
+    pub(crate) pins: Vec<CertPin>,
+    pub(crate) client_cert: Option<ClientCert>,
+}
+
+impl TlsOptions {
+    /// Build the client config, reading any files that the roots or client
+    /// certificate come from.
+    pub(crate) async fn client_config(&self) -> Result<rustls::ClientConfig, io::Error> {
+        let mut root_store = rustls::RootCertStore::empty();
+        let mut cert_pem = io::Cursor::new(CERT_BYTES);
+        root_store.add_parsable_certificates(
+            rustls_pemfile::read_all(&mut cert_pem)
+                .expect("a valid ngrok root certificate")
+                .into_iter()
+                .filter_map(|it| match it {
+                    Item::X509Certificate(bs) => Some(bs),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .as_slice(),
+        );
+
+        for source in &self.root_cas {
+            let (added, _) = root_store.add_parsable_certificates(&source.certificates().await?);
+            if added == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no valid root certificates in {}", source.describe()),
+                ));
+            }
+        }
+
+        if self.native_roots {
+            root_store.add_parsable_certificates(native_roots().await?);
+        }
+
+        let webpki = WebPkiVerifier::new(root_store, None);
+        let verifier: Arc<dyn ServerCertVerifier> = if self.pins.is_empty() {
+            Arc::new(webpki)
+        } else {
+            Arc::new(PinningVerifier {
+                inner: webpki,
+                pins: self.pins.clone(),
+            })
+        };
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier);
+        match &self.client_cert {
+            Some(cert) => {
+                let chain = cert
+                    .chain
+                    .certificates()
+                    .await?
+                    .into_iter()
+                    .map(Certificate)
+                    .collect();
+                let key = rustls::PrivateKey(cert.key.private_key().await?);
+                builder
+                    .with_single_cert(chain, key)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+            }
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+// Verifies the server's certificate chain as usual, and then requires that at
+// least one of its certificates matches a pin.
+struct PinningVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<CertPin>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .any(|cert| self.pins.iter().any(|pin| pin.matches(&cert.0)));
+        if !pinned {
+            return Err(rustls::Error::General(
+                "server certificate chain doesn't match any pin".into(),
+            ));
+        }
+        Ok(verified)
+    }
+}
+
+// A DER-encoded element.
+struct Der<'a> {
+    tag: u8,
+    // The whole element, including its tag and length.
+    raw: &'a [u8],
+    contents: &'a [u8],
+}
+
+// Split a DER element off of the front of the input, returning it and the rest
+// of the input.
+fn der_element(input: &[u8]) -> Option<(Der<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let (len, rest) = if len & 0x80 == 0 {
+        (len as usize, rest)
+    } else {
+        let octets = (len & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let (octets, rest) = rest.split_at(octets);
+        let len = octets
+            .iter()
+            .fold(0usize, |len, octet| len << 8 | *octet as usize);
+        (len, rest)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header_len = input.len() - rest.len();
+    let (contents, rest) = rest.split_at(len);
+    let der = Der {
+        tag,
+        raw: &input[..header_len + len],
+        contents,
+    };
+    Some((der, rest))
+}
+
+// Find the DER-encoded SubjectPublicKeyInfo in a DER-encoded X.509
+// certificate.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+
+    let sequence = |input| der_element(input).filter(|(der, _)| der.tag == SEQUENCE);
+
+    let (cert, _) = sequence(cert)?;
+    let (tbs, _) = sequence(cert.contents)?;
+    let mut fields = tbs.contents;
+    if fields.first() == Some(&VERSION) {
+        fields = der_element(fields)?.1;
+    }
+    // The serial number, signature algorithm, issuer, validity, and subject
+    // come first.
+    for _ in 0..5 {
+        fields = der_element(fields)?.1;
+    }
+    sequence(fields).map(|(spki, _)| spki.raw)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CERT_PEM: &[u8] = include_bytes!("../../examples/domain.crt");
+    const KEY_PEM: &[u8] = include_bytes!("../../examples/domain.key");
+
+    fn cert_der() -> Vec<u8> {
+        match rustls_pemfile::read_one(&mut io::Cursor::new(CERT_PEM)) {
+            Ok(Some(Item::X509Certificate(der))) => der,
+            other => panic!("expected a certificate, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_spki() {
+        let cert = cert_der();
+        let spki = subject_public_key_info(&cert).unwrap();
+        // An RSA key's algorithm identifier comes first.
+        assert_eq!(0x30, spki[0]);
+        assert!(spki
+            .windows(9)
+            .any(|oid| oid == [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01]));
+        assert!(subject_public_key_info(&cert[..cert.len() / 2]).is_none());
+        assert!(subject_public_key_info(&[]).is_none());
+    }
+
+    #[test]
+    fn test_pins() {
+        let cert = cert_der();
+        let cert_hash: [u8; 32] = digest(&SHA256, &cert).as_ref().try_into().unwrap();
+        let spki_hash: [u8; 32] = digest(&SHA256, subject_public_key_info(&cert).unwrap())
+            .as_ref()
+            .try_into()
+            .unwrap();
+
+        assert!(CertPin::Certificate(cert_hash).matches(&cert));
+        assert!(CertPin::PublicKey(spki_hash).matches(&cert));
+        assert!(!CertPin::Certificate(spki_hash).matches(&cert));
+        assert!(!CertPin::PublicKey(cert_hash).matches(&cert));
+    }
+
+    #[tokio::test]
+    async fn test_root_cas() {
+        let opts = TlsOptions {
+            root_cas: vec![PemSource::Bytes(CERT_PEM.into())],
+            ..Default::default()
+        };
+        opts.client_config().await.unwrap();
+
+        let opts = TlsOptions {
+            root_cas: vec![PemSource::Bytes(b"not a certificate".as_slice().into())],
+            ..Default::default()
+        };
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            opts.client_config().await.unwrap_err().kind()
+        );
+
+        let opts = TlsOptions {
+            root_cas: vec![PemSource::File("/nonexistent/ca.pem".into())],
+            ..Default::default()
+        };
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            opts.client_config().await.unwrap_err().kind()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_native_roots() {
+        let opts = TlsOptions {
+            native_roots: true,
+            ..Default::default()
+        };
+        opts.client_config().await.unwrap();
+        // They're only loaded the first time.
+        assert!(std::ptr::eq(
+            native_roots().await.unwrap(),
+            native_roots().await.unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_cert() {
+        let opts = TlsOptions {
+            client_cert: Some(ClientCert {
+                chain: PemSource::Bytes(CERT_PEM.into()),
+                key: PemSource::Bytes(KEY_PEM.into()),
+            }),
+            ..Default::default()
+        };
+        let config = opts.client_config().await.unwrap();
+        assert!(config.client_auth_cert_resolver.has_certs());
+
+        let opts = TlsOptions {
+            client_cert: Some(ClientCert {
+                chain: PemSource::Bytes(CERT_PEM.into()),
+                key: PemSource::Bytes(CERT_PEM.into()),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            opts.client_config().await.unwrap_err().kind()
+        );
+    }
+}
@@ -0,0 +1,680 @@
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    io,
+    pin::Pin,
+    sync::{
+        Arc,
+        Mutex as StdMutex,
+    },
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+use futures::FutureExt;
+use muxado::{
+    heartbeat::{
+        Heartbeat,
+        HeartbeatConfig,
+    },
+    typed::{
+        StreamType,
+        Typed,
+        TypedAccept,
+        TypedOpen,
+        TypedSession,
+        TypedStream,
+    },
+};
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+        DuplexStream,
+    },
+    sync::{
+        watch,
+        Mutex,
+    },
+    task::JoinHandle,
+};
+use tracing::{
+    debug,
+    warn,
+};
+
+#[doc(inline)]
+pub use crate::internals::proto::{
+    EdgeType,
+    ProxyHeader,
+};
+use crate::{
+    internals::{
+        proto::{
+            Auth,
+            AuthResp,
+            AuthRespExtra,
+            Bind,
+            BindResp,
+            BindRespExtra,
+            CommandResp,
+            Restart,
+            SrvInfo,
+            SrvInfoResp,
+            StartTunnelWithLabel,
+            StartTunnelWithLabelResp,
+            Stop,
+            Unbind,
+            UnbindResp,
+            Update,
+            AUTH_REQ,
+            BIND_LABELED_REQ,
+            BIND_REQ,
+            PROXY_REQ,
+            RESTART_REQ,
+            SRV_INFO_REQ,
+            STOP_REQ,
+            UNBIND_REQ,
+            UPDATE_REQ,
+            VERSION,
+        },
+        rpc::read_json,
+    },
+    session::{
+        ConnectCallback,
+        ConnectError,
+        IoStream,
+        SessionBuilder,
+    },
+    Session,
+};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// The RPCs answered by the [MockServer].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Rpc {
+    /// The session authentication RPC.
+    Auth,
+    /// The RPC to bind a tunnel backing an HTTP, TCP, or TLS endpoint.
+    Bind,
+    /// The RPC to start a labeled tunnel.
+    StartTunnelWithLabel,
+    /// The RPC to close a tunnel.
+    Unbind,
+    /// The RPC to get information about the server.
+    SrvInfo,
+}
+
+impl Rpc {
+    fn from_stream_type(typ: StreamType) -> Option<Self> {
+        Some(match typ {
+            AUTH_REQ => Rpc::Auth,
+            BIND_REQ => Rpc::Bind,
+            BIND_LABELED_REQ => Rpc::StartTunnelWithLabel,
+            UNBIND_REQ => Rpc::Unbind,
+            SRV_INFO_REQ => Rpc::SrvInfo,
+            _ => return None,
+        })
+    }
+}
+
+/// An in-process ngrok server.
+///
+/// The mock speaks the real tunneling protocol over an in-memory transport,
+/// and plugs into a [SessionBuilder] via
+/// [SessionBuilder::with_connect_callback]. It answers the RPCs needed to
+/// authenticate and bind tunnels, and can push connections into bound tunnels
+/// as if they had arrived at an ngrok edge.
+///
+/// Every call to its [ConnectCallback] establishes a new transport to the
+/// server, so reconnecting sessions will be served by the same mock.
+#[derive(Clone, Default)]
+pub struct MockServer {
+    inner: Arc<MockInner>,
+}
+
+struct MockInner {
+    state: StdMutex<MockState>,
+    changed: watch::Sender<()>,
+}
+
+impl Default for MockInner {
+    fn default() -> Self {
+        MockInner {
+            state: Default::default(),
+            changed: watch::channel(()).0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    refuse_connections: bool,
+    auth_error: Option<String>,
+    rpc_errors: HashMap<Rpc, VecDeque<String>>,
+    rpc_delays: HashMap<Rpc, Duration>,
+    latency: Option<Duration>,
+    next_id: u64,
+    connections: usize,
+    sessions: HashMap<u64, MockSession>,
+    tunnels: HashMap<String, u64>,
+    auths: Vec<Auth>,
+}
+
+struct MockSession {
+    open: Arc<Mutex<Box<dyn TypedOpen + Send>>>,
+    relay: JoinHandle<()>,
+}
+
+impl MockState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}_{}", self.next_id)
+    }
+
+    fn take_error(&mut self, rpc: Rpc) -> Option<String> {
+        if rpc == Rpc::Auth && self.auth_error.is_some() {
+            return self.auth_error.clone();
+        }
+        self.rpc_errors.get_mut(&rpc).and_then(VecDeque::pop_front)
+    }
+}
+
+impl MockServer {
+    /// Create a new mock server.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Get a [ConnectCallback] that connects to this server.
+    pub fn connect_callback(&self) -> ConnectCallback {
+        let inner = self.inner.clone();
+        Arc::new(move |_addr, _tls_config| {
+            let inner = inner.clone();
+            async move { inner.accept_transport() }.boxed()
+        })
+    }
+
+    /// Create a new [SessionBuilder] that connects to this server.
+    pub fn session_builder(&self) -> SessionBuilder {
+        let mut builder = Session::builder();
+        builder.with_connect_callback(self.connect_callback());
+        builder
+    }
+
+    /// Reject all authentication attempts with the given error until
+    /// [MockServer::accept_auth] is called.
+    pub fn reject_auth(&self, error: impl Into<String>) {
+        self.inner
+            .update(|state| state.auth_error = Some(error.into()));
+    }
+
+    /// Stop rejecting authentication attempts.
+    pub fn accept_auth(&self) {
+        self.inner.update(|state| state.auth_error = None);
+    }
+
+    /// Respond to the next call of the given RPC with an error.
+    ///
+    /// Call multiple times to fail multiple calls.
+    pub fn fail_next(&self, rpc: Rpc, error: impl Into<String>) {
+        self.inner.update(|state| {
+            state
+                .rpc_errors
+                .entry(rpc)
+                .or_default()
+                .push_back(error.into())
+        });
+    }
+
+    /// Wait for the given duration before responding to each call of the
+    /// given RPC.
+    pub fn delay(&self, rpc: Rpc, delay: Duration) {
+        self.inner
+            .update(|state| state.rpc_delays.insert(rpc, delay));
+    }
+
+    /// Delay everything sent over new transports by the given duration in each
+    /// direction, as if the server were far away.
+    pub fn latency(&self, latency: Duration) {
+        self.inner.update(|state| state.latency = Some(latency));
+    }
+
+    /// Refuse new transport connections, as if the server were unreachable.
+    pub fn refuse_connections(&self, refuse: bool) {
+        self.inner.update(|state| state.refuse_connections = refuse);
+    }
+
+    /// Abruptly drop all established transports.
+    ///
+    /// Tunnels bound on the dropped sessions are forgotten.
+    pub fn drop_transports(&self) {
+        self.inner.update(|state| {
+            for (_id, sess) in state.sessions.drain() {
+                sess.relay.abort();
+            }
+            state.tunnels.clear();
+        });
+    }
+
+    /// The total number of transport connections accepted by this server.
+    pub fn connections(&self) -> usize {
+        self.inner.state.lock().unwrap().connections
+    }
+
+    /// The number of sessions currently connected to this server.
+    pub fn sessions(&self) -> usize {
+        self.inner.state.lock().unwrap().sessions.len()
+    }
+
+    /// The IDs of the tunnels currently bound on this server.
+    pub fn tunnels(&self) -> Vec<String> {
+        self.inner
+            .state
+            .lock()
+            .unwrap()
+            .tunnels
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Wait until a tunnel with the given ID is bound.
+    pub async fn wait_for_tunnel(&self, id: impl AsRef<str>) {
+        let id = id.as_ref();
+        self.inner
+            .wait_until(|state| state.tunnels.contains_key(id))
+            .await
+    }
+
+    /// Wait until the server has accepted at least `count` connections.
+    pub async fn wait_for_connections(&self, count: usize) {
+        self.inner
+            .wait_until(|state| state.connections >= count)
+            .await
+    }
+
+    /// Wait until no sessions are connected to the server.
+    pub async fn wait_for_disconnect(&self) {
+        self.inner
+            .wait_until(|state| state.sessions.is_empty())
+            .await
+    }
+
+    /// Open a new proxied connection to the tunnel identified by
+    /// [ProxyHeader::id].
+    ///
+    /// The returned [MockConn] is the edge side of the connection, i.e. bytes
+    /// written to it will be read from the [crate::Conn] in the tunnel.
+    pub async fn push_conn(&self, header: ProxyHeader) -> Result<MockConn, io::Error> {
+        let open = {
+            let state = self.inner.state.lock().unwrap();
+            state
+                .tunnels
+                .get(&header.id)
+                .and_then(|sess_id| state.sessions.get(sess_id))
+                .map(|sess| sess.open.clone())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("tunnel not bound: {}", header.id),
+                    )
+                })?
+        };
+
+        let mut stream = open
+            .lock()
+            .await
+            .open_typed(PROXY_REQ)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))?;
+
+        let header = serde_json::to_vec(&header)?;
+        stream.write_i64_le(header.len() as i64).await?;
+        stream.write_all(&header).await?;
+
+        Ok(MockConn { stream })
+    }
+
+    /// Send a stop command to the most recently connected session.
+    ///
+    /// Resolves to the error reported by the agent, if any.
+    pub async fn stop(&self) -> Result<Result<(), String>, io::Error> {
+        self.command(STOP_REQ, Stop {}).await
+    }
+
+    /// Send a restart command to the most recently connected session.
+    ///
+    /// Resolves to the error reported by the agent, if any.
+    pub async fn restart(&self) -> Result<Result<(), String>, io::Error> {
+        self.command(RESTART_REQ, Restart {}).await
+    }
+
+    /// Send an update command to the most recently connected session.
+    ///
+    /// Resolves to the error reported by the agent, if any.
+    pub async fn update(
+        &self,
+        version: impl Into<String>,
+        permit_major_version: bool,
+    ) -> Result<Result<(), String>, io::Error> {
+        let req = Update {
+            version: version.into(),
+            permit_major_version,
+        };
+        self.command(UPDATE_REQ, req).await
+    }
+
+    async fn command(
+        &self,
+        typ: StreamType,
+        req: impl Serialize,
+    ) -> Result<Result<(), String>, io::Error> {
+        self.raw_command(typ, &serde_json::to_vec(&req)?).await
+    }
+
+    // Send a command with a request body that isn't necessarily valid, or
+    // complete.
+    pub(crate) async fn raw_command(
+        &self,
+        typ: StreamType,
+        body: &[u8],
+    ) -> Result<Result<(), String>, io::Error> {
+        let open = {
+            let state = self.inner.state.lock().unwrap();
+            state
+                .sessions
+                .iter()
+                .max_by_key(|(id, _)| **id)
+                .map(|(_, sess)| sess.open.clone())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no sessions"))?
+        };
+
+        let mut stream = open
+            .lock()
+            .await
+            .open_typed(typ)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionReset, e))?;
+
+        stream.write_all(body).await?;
+        let resp: CommandResp = read_json(&mut *stream).await?;
+
+        Ok(if resp.error.is_empty() {
+            Ok(())
+        } else {
+            Err(resp.error)
+        })
+    }
+
+    /// The auth requests received by this server, in order.
+    #[cfg(test)]
+    pub(crate) fn auths(&self) -> Vec<Auth> {
+        self.inner.state.lock().unwrap().auths.clone()
+    }
+}
+
+// Copy from one end to the other, holding each chunk for the given delay.
+async fn delayed_copy(
+    mut from: impl AsyncRead + Unpin,
+    mut to: impl AsyncWrite + Unpin,
+    delay: Duration,
+) {
+    let mut buf = vec![0; BUFFER_SIZE];
+    while let Ok(n @ 1..) = from.read(&mut buf).await {
+        tokio::time::sleep(delay).await;
+        if to.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = to.shutdown().await;
+}
+
+impl MockInner {
+    fn update<T>(&self, f: impl FnOnce(&mut MockState) -> T) -> T {
+        let res = f(&mut self.state.lock().unwrap());
+        self.changed.send_replace(());
+        res
+    }
+
+    async fn wait_until(&self, mut f: impl FnMut(&MockState) -> bool) {
+        let mut changed = self.changed.subscribe();
+        loop {
+            if f(&self.state.lock().unwrap()) {
+                return;
+            }
+            // The sender lives as long as we do, so this can't fail.
+            let _ = changed.changed().await;
+        }
+    }
+
+    fn accept_transport(self: Arc<Self>) -> Result<Box<dyn IoStream>, ConnectError> {
+        let (refuse, latency) = {
+            let state = self.state.lock().unwrap();
+            (state.refuse_connections, state.latency)
+        };
+        if refuse {
+            return Err(ConnectError::Tcp(io::ErrorKind::ConnectionRefused.into()));
+        }
+
+        // Relay between two duplex pairs so that the transport can be dropped
+        // out from under both ends by aborting the relay task.
+        let (client, mut client_relay) = tokio::io::duplex(BUFFER_SIZE);
+        let (mut server_relay, server) = tokio::io::duplex(BUFFER_SIZE);
+        let relay = tokio::spawn(async move {
+            match latency {
+                Some(latency) => {
+                    let (client_rx, client_tx) = tokio::io::split(client_relay);
+                    let (server_rx, server_tx) = tokio::io::split(server_relay);
+                    futures::join!(
+                        delayed_copy(client_rx, server_tx, latency),
+                        delayed_copy(server_rx, client_tx, latency),
+                    );
+                }
+                None => {
+                    let _ =
+                        tokio::io::copy_bidirectional(&mut client_relay, &mut server_relay).await;
+                }
+            }
+        });
+
+        tokio::spawn(self.serve(server, relay));
+
+        Ok(Box::new(client))
+    }
+
+    async fn serve(self: Arc<Self>, io: DuplexStream, relay: JoinHandle<()>) {
+        let typed = Typed::new(muxado::SessionBuilder::new(io).server().start());
+        let (heartbeat, _ctl) =
+            match Heartbeat::start(typed, HeartbeatConfig::<fn(Duration)>::default()).await {
+                Ok(hb) => hb,
+                Err(error) => {
+                    warn!(%error, "failed to start mock heartbeat");
+                    relay.abort();
+                    return;
+                }
+            };
+        let (open, mut accept) = heartbeat.split_typed();
+
+        let sess_id = self.update(|state| {
+            state.next_id += 1;
+            state.connections += 1;
+            state.sessions.insert(
+                state.next_id,
+                MockSession {
+                    open: Arc::new(Mutex::new(Box::new(open))),
+                    relay,
+                },
+            );
+            state.next_id
+        });
+
+        while let Ok(stream) = accept.accept_typed().await {
+            tokio::spawn(self.clone().handle_rpc(sess_id, stream));
+        }
+
+        debug!(sess_id, "mock session closed");
+
+        self.update(|state| {
+            if let Some(sess) = state.sessions.remove(&sess_id) {
+                sess.relay.abort();
+            }
+            state.tunnels.retain(|_, id| *id != sess_id);
+        });
+    }
+
+    async fn handle_rpc(self: Arc<Self>, sess_id: u64, mut stream: TypedStream) {
+        let rpc = if let Some(rpc) = Rpc::from_stream_type(stream.typ()) {
+            rpc
+        } else {
+            warn!(typ = ?stream.typ(), "unhandled stream type");
+            return;
+        };
+
+        let res = match rpc {
+            Rpc::Auth => {
+                self.respond(&mut stream, rpc, |state, req: Auth| {
+                    let client_id = if req.client_id.is_empty() {
+                        state.next_id("sess")
+                    } else {
+                        req.client_id.clone()
+                    };
+                    state.auths.push(req);
+                    AuthResp {
+                        version: VERSION.into(),
+                        client_id,
+                        extra: AuthRespExtra {
+                            version: Some(env!("CARGO_PKG_VERSION").into()),
+                            region: Some("mock".into()),
+                            cookie: Some("mock-cookie".into()),
+                            account_name: Some("mock-account".into()),
+                            plan_name: Some("mock-plan".into()),
+                            banner: Some("mock-banner".into()),
+                            session_duration: Some(
+                                Duration::from_secs(8 * 60 * 60).as_nanos() as i64
+                            ),
+                        },
+                    }
+                })
+                .await
+            }
+            Rpc::Bind => {
+                self.respond(&mut stream, rpc, |state, req: Bind<serde_json::Value>| {
+                    let id = if req.client_id.is_empty() {
+                        state.next_id("tun")
+                    } else {
+                        req.client_id
+                    };
+                    state.tunnels.insert(id.clone(), sess_id);
+                    BindResp {
+                        url: format!("{}://{id}.mock.ngrok", req.proto),
+                        client_id: id,
+                        proto: req.proto,
+                        bind_opts: req.opts,
+                        extra: BindRespExtra {
+                            token: "mock-token".into(),
+                        },
+                    }
+                })
+                .await
+            }
+            Rpc::StartTunnelWithLabel => {
+                self.respond(&mut stream, rpc, |state, _req: StartTunnelWithLabel| {
+                    let id = state.next_id("tun");
+                    state.tunnels.insert(id.clone(), sess_id);
+                    StartTunnelWithLabelResp { id }
+                })
+                .await
+            }
+            Rpc::Unbind => {
+                self.respond(&mut stream, rpc, |state, req: Unbind| {
+                    state.tunnels.remove(&req.client_id);
+                    UnbindResp {}
+                })
+                .await
+            }
+            Rpc::SrvInfo => {
+                self.respond(&mut stream, rpc, |_state, _req: SrvInfo| SrvInfoResp {
+                    region: "mock".into(),
+                })
+                .await
+            }
+        };
+
+        if let Err(error) = res {
+            debug!(?rpc, %error, "error handling mock rpc");
+        }
+    }
+
+    async fn respond<Req, Resp>(
+        &self,
+        stream: &mut TypedStream,
+        rpc: Rpc,
+        f: impl FnOnce(&mut MockState, Req) -> Resp,
+    ) -> Result<(), io::Error>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+    {
+        let req = read_json::<Req>(&mut **stream).await?;
+
+        let delay = self.state.lock().unwrap().rpc_delays.get(&rpc).copied();
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        let resp = self.update(|state| match state.take_error(rpc) {
+            Some(error) => serde_json::json!({ "Error": error }),
+            None => serde_json::to_value(f(state, req)).expect("serializable response"),
+        });
+
+        stream.write_all(&serde_json::to_vec(&resp)?).await?;
+        stream.shutdown().await
+    }
+}
+
+/// The edge side of a connection pushed into a tunnel by a [MockServer].
+pub struct MockConn {
+    stream: TypedStream,
+}
+
+impl AsyncRead for MockConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MockConn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut *self.stream).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut *self.stream).poll_flush(cx)
+    }
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut *self.stream).poll_shutdown(cx)
+    }
+}
@@ -1,14 +1,32 @@
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr,
+    },
     pin::Pin,
+    sync::Arc,
     task::{
         Context,
         Poll,
     },
+    time::Duration,
 };
 
+use async_rustls::{
+    rustls::{
+        Certificate,
+        ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
 use async_trait::async_trait;
+use bytes::BytesMut;
+#[cfg(feature = "hyper")]
+use futures::ready;
 use futures::Stream;
 #[cfg(feature = "hyper")]
 use hyper::server::accept::Accept;
@@ -20,11 +38,33 @@ use thiserror::Error;
 use tokio::{
     io::{
         AsyncRead,
+        AsyncReadExt,
         AsyncWrite,
     },
-    sync::mpsc::Receiver,
+    sync::{
+        mpsc::Receiver,
+        watch,
+    },
+    time::timeout,
+};
+use tokio_util::compat::{
+    Compat,
+    FuturesAsyncReadCompatExt,
+    TokioAsyncReadCompatExt,
+};
+use tracing::{
+    debug,
+    warn,
+};
+use url::{
+    Host,
+    Url,
 };
 
+#[doc(inline)]
+pub use crate::internals::proto::EdgeType;
+#[doc(inline)]
+pub use crate::tunnel_ext::ForwardOptions;
 use crate::{
     config::{
         HttpTunnelBuilder,
@@ -32,22 +72,74 @@ use crate::{
         TcpTunnelBuilder,
         TlsTunnelBuilder,
     },
-    internals::raw_session::RpcError,
+    internals::{
+        proto::ProxyHeader,
+        raw_session::RpcError,
+    },
+    proxy_proto::{
+        ProxyProtoError,
+        ProxyProtoHeader,
+    },
     Session,
 };
 
+// How long to wait for the PROXY protocol header, which the edge sends as soon
+// as the connection is opened.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Errors arising when accepting a [Conn] from an ngrok tunnel.
 #[derive(Error, Debug, Clone, Copy)]
 pub enum AcceptError {
     /// An error occurred in the underlying transport protocol.
     #[error("transport error")]
     Transport(#[from] MuxadoError),
+    /// The session was closed.
+    #[error("session closed")]
+    SessionClosed,
+    /// A connection's PROXY protocol header couldn't be decoded.
+    ///
+    /// Only that connection is dropped, and the tunnel can continue to be
+    /// accepted from.
+    #[error("invalid PROXY protocol header")]
+    ProxyProto(#[from] ProxyProtoError),
+    /// The TLS handshake with a client failed, for a tunnel that terminates
+    /// TLS at the agent.
+    ///
+    /// Only that connection is dropped, and the tunnel can continue to be
+    /// accepted from.
+    #[error("TLS handshake failed: {0}")]
+    TlsHandshake(io::ErrorKind),
+    /// The tunnel couldn't be bound again after its session reconnected, and
+    /// has been closed.
+    ///
+    /// The session and its other tunnels are unaffected. The server's error is
+    /// reported by [SessionEvent::TunnelRebindFailed](crate::session::SessionEvent::TunnelRebindFailed).
+    #[error("failed to rebind tunnel")]
+    Rebind,
 }
 
-pub(crate) struct TunnelInner {
+impl AcceptError {
+    /// Whether the error only affected a single connection, rather than the
+    /// whole tunnel.
+    pub fn is_per_connection(&self) -> bool {
+        matches!(
+            self,
+            AcceptError::ProxyProto(_) | AcceptError::TlsHandshake(_)
+        )
+    }
+}
+
+/// The parts of a tunnel that are assigned by the ngrok server, and may change
+/// when the tunnel is rebound after its session reconnects.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TunnelBinding {
     pub(crate) id: String,
-    pub(crate) proto: String,
     pub(crate) url: String,
+}
+
+pub(crate) struct TunnelInner {
+    pub(crate) binding: watch::Receiver<TunnelBinding>,
+    pub(crate) proto: String,
     pub(crate) labels: HashMap<String, String>,
     pub(crate) forwards_to: String,
     pub(crate) metadata: String,
@@ -72,7 +164,10 @@ macro_rules! tunnel_trait {
             + 'static
         {
             /// The ID of this tunnel, assigned by the remote server.
-            fn id(&self) -> &str;
+            ///
+            /// This may change if the tunnel is rebound after its session
+            /// reconnects.
+            fn id(&self) -> String;
             /// Get the forwards_to metadata for this tunnel.
             fn forwards_to(&self) -> &str;
             /// Get the user metadata for this tunnel.
@@ -81,6 +176,11 @@ macro_rules! tunnel_trait {
             ///
             /// This is an RPC call that must be `.await`ed.
             async fn close(&mut self) -> Result<(), RpcError>;
+            /// Wait for the tunnel to be rebound with a different ID or URL
+            /// after its session reconnects, returning the new URL.
+            ///
+            /// Returns [None] once the tunnel is closed.
+            async fn rebound(&mut self) -> Option<String>;
         }
     }
 }
@@ -94,7 +194,9 @@ tunnel_trait!(+ Accept<Conn = Conn, Error = AcceptError>);
 /// An ngrok tunnel that supports getting the URL it was started for.
 pub trait UrlTunnel: Tunnel {
     /// The URL that this tunnel backs.
-    fn url(&self) -> &str;
+    ///
+    /// This may change if the tunnel is rebound after its session reconnects.
+    fn url(&self) -> String;
 }
 
 /// An ngrok tunnel that supports getting the protocol it uses at the ngrok edge.
@@ -112,10 +214,176 @@ pub trait LabelsTunnel: Tunnel {
 /// A connection from an ngrok tunnel.
 ///
 /// This implements [AsyncRead]/[AsyncWrite], as well as providing access to the
-/// address from which the connection to the ngrok edge originated.
+/// address from which the connection to the ngrok edge originated and the
+/// rest of the [ConnInfo] sent along with it.
 pub struct Conn {
-    pub(crate) remote_addr: SocketAddr,
-    pub(crate) stream: TypedStream,
+    pub(crate) info: ConnInfo,
+    pub(crate) stream: ConnStream,
+    // Read from the stream by Conn::peek, but not yet by the application.
+    pub(crate) peeked: BytesMut,
+}
+
+// The stream underlying a Conn.
+pub(crate) enum ConnStream {
+    Plain(TypedStream),
+    // Decrypted from the plain stream.
+    Tls(Box<Compat<TlsStream<Compat<ConnStream>>>>),
+}
+
+impl From<TypedStream> for ConnStream {
+    fn from(stream: TypedStream) -> Self {
+        ConnStream::Plain(stream)
+    }
+}
+
+/// The TLS session negotiated with a client, for tunnels that terminate TLS at
+/// the agent.
+#[derive(Clone, Debug, Default)]
+pub struct TlsInfo {
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    peer_certificates: Vec<Certificate>,
+}
+
+impl TlsInfo {
+    /// The server name that the client asked for via SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// The protocol negotiated via ALPN.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The client's certificate chain, end-entity first, if the server config
+    /// asked for one and the client presented it.
+    ///
+    /// It has already been verified by the server config's client certificate
+    /// verifier.
+    pub fn peer_certificates(&self) -> &[Certificate] {
+        &self.peer_certificates
+    }
+}
+
+/// Information about a [Conn], sent by the ngrok edge ahead of the connection
+/// itself.
+#[derive(Clone, Debug)]
+pub struct ConnInfo {
+    // None if the edge sent an address that couldn't be parsed.
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) client_addr: String,
+    // The tunnel's URL when the connection arrived.
+    pub(crate) tunnel_url: String,
+    pub(crate) tunnel_id: String,
+    pub(crate) proto: String,
+    pub(crate) edge_type: EdgeType,
+    pub(crate) passthrough_tls: bool,
+    pub(crate) proxy_header: Option<ProxyProtoHeader>,
+    pub(crate) tls: Option<TlsInfo>,
+}
+
+impl ConnInfo {
+    pub(crate) fn from_header(header: ProxyHeader, tunnel_url: String) -> Self {
+        let remote_addr = header
+            .client_addr
+            .parse()
+            .map_err(|error| {
+                warn!(
+                    client_addr = header.client_addr,
+                    %error,
+                    "invalid remote addr for tunnel connection",
+                );
+            })
+            .ok();
+        ConnInfo {
+            remote_addr,
+            client_addr: header.client_addr,
+            tunnel_url,
+            tunnel_id: header.id,
+            proto: header.proto,
+            edge_type: header.edge_type,
+            passthrough_tls: header.passthrough_tls,
+            proxy_header: None,
+            tls: None,
+        }
+    }
+
+    /// Get the client address that initiated the connection to the ngrok edge.
+    ///
+    /// If the tunnel decodes PROXY protocol headers, this is the source
+    /// address from the header when it has one.
+    ///
+    /// If the edge sent an address that couldn't be parsed, this is
+    /// `0.0.0.0:0`, and the original is available from
+    /// [ConnInfo::client_addr].
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+            .unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, 0).into())
+    }
+
+    // The address of the ngrok edge that the client connected to, as far as
+    // the tunnel's URL tells. Hostnames aren't resolved, so the IP is
+    // unspecified unless the URL has an IP address, and labeled tunnels have
+    // neither.
+    pub(crate) fn edge_addr(&self) -> SocketAddr {
+        let url = Url::parse(&self.tunnel_url).ok();
+        let ip: IpAddr = match url.as_ref().and_then(Url::host) {
+            Some(Host::Ipv4(ip)) => ip.into(),
+            Some(Host::Ipv6(ip)) => ip.into(),
+            _ => Ipv4Addr::UNSPECIFIED.into(),
+        };
+        let port = url
+            .as_ref()
+            .and_then(|url| match url.scheme() {
+                "tls" => url.port().or(Some(443)),
+                _ => url.port_or_known_default(),
+            })
+            .unwrap_or_default();
+        (ip, port).into()
+    }
+
+    /// Get the client address exactly as it was sent by the ngrok edge.
+    pub fn client_addr(&self) -> &str {
+        &self.client_addr
+    }
+
+    /// Get the ID of the tunnel that the connection arrived on.
+    pub fn tunnel_id(&self) -> &str {
+        &self.tunnel_id
+    }
+
+    /// Get the protocol of the connection at the ngrok edge, e.g. `https` or
+    /// `tcp`.
+    pub fn proto(&self) -> &str {
+        &self.proto
+    }
+
+    /// Get the type of edge that the connection arrived on.
+    ///
+    /// This distinguishes e.g. connections from an HTTPS edge from those from
+    /// a TLS edge for the same labeled tunnel.
+    pub fn edge_type(&self) -> EdgeType {
+        self.edge_type
+    }
+
+    /// Whether the ngrok edge passed TLS through to the tunnel rather than
+    /// terminating it.
+    pub fn passthrough_tls(&self) -> bool {
+        self.passthrough_tls
+    }
+
+    /// Get the PROXY protocol header that was stripped from the connection,
+    /// if the tunnel decodes them.
+    pub fn proxy_header(&self) -> Option<&ProxyProtoHeader> {
+        self.proxy_header.as_ref()
+    }
+
+    /// Get the TLS session negotiated with the client, if the tunnel
+    /// terminates TLS at the agent.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
 }
 
 impl Stream for TunnelInner {
@@ -135,30 +403,57 @@ impl Accept for TunnelInner {
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
-        self.poll_next(cx)
+        poll_accept_conn(self, cx)
+    }
+}
+
+// Accept the next connection for a hyper server, skipping over errors that
+// only affect a single connection rather than letting them end the server.
+#[cfg(feature = "hyper")]
+pub(crate) fn poll_accept_conn<S>(
+    mut conns: Pin<&mut S>,
+    cx: &mut Context<'_>,
+) -> Poll<Option<Result<Conn, AcceptError>>>
+where
+    S: Stream<Item = Result<Conn, AcceptError>> + ?Sized,
+{
+    loop {
+        match ready!(conns.as_mut().poll_next(cx)) {
+            Some(Err(error)) if error.is_per_connection() => {
+                warn!(%error, "dropping tunnel connection");
+            }
+            res => return Poll::Ready(res),
+        }
     }
 }
 
 impl TunnelInner {
     /// Get this tunnel's ID as returned by the ngrok server.
-    pub fn id(&self) -> &str {
-        &self.id
+    pub fn id(&self) -> String {
+        self.binding.borrow().id.clone()
     }
 
     /// Get the URL for this tunnel.
     /// Labeled tunnels will return an empty string.
-    pub fn url(&self) -> &str {
-        &self.url
+    pub fn url(&self) -> String {
+        self.binding.borrow().url.clone()
     }
 
     /// Close the tunnel.
     /// This is an RPC call and needs to be `.await`ed.
     pub async fn close(&mut self) -> Result<(), RpcError> {
-        self.session.close_tunnel(&self.id).await?;
+        self.session.close_tunnel(self.id()).await?;
         self.incoming.close();
         Ok(())
     }
 
+    /// Wait for the tunnel to be rebound with a different ID or URL.
+    /// Returns [None] once the tunnel is closed.
+    pub async fn rebound(&mut self) -> Option<String> {
+        self.binding.changed().await.ok()?;
+        Some(self.binding.borrow_and_update().url.clone())
+    }
+
     /// Get the protocol that this tunnel uses.
     pub fn proto(&self) -> &str {
         &self.proto
@@ -184,7 +479,119 @@ impl TunnelInner {
 impl Conn {
     /// Get the client address that initiated the connection to the ngrok edge.
     pub fn remote_addr(&self) -> SocketAddr {
-        self.remote_addr
+        self.info.remote_addr()
+    }
+
+    /// Get the information sent by the ngrok edge about this connection.
+    pub fn info(&self) -> &ConnInfo {
+        &self.info
+    }
+
+    /// Get the type of edge that the connection arrived on.
+    pub fn edge_type(&self) -> EdgeType {
+        self.info.edge_type()
+    }
+
+    /// Get the protocol of the connection at the ngrok edge.
+    pub fn proto(&self) -> &str {
+        self.info.proto()
+    }
+
+    /// Whether the ngrok edge passed TLS through to the tunnel rather than
+    /// terminating it.
+    pub fn passthrough_tls(&self) -> bool {
+        self.info.passthrough_tls()
+    }
+
+    /// Get the PROXY protocol header that was stripped from the connection,
+    /// if the tunnel decodes them.
+    ///
+    /// Tunnels decode headers when started with `decode_proxy_proto`, which
+    /// for endpoints also has the edge send them, replacing the version set by
+    /// `proxy_proto` (`ProxyProto::None` turns both off). [Conn::remote_addr] then returns the client address
+    /// that the header carries. Connections with a missing or malformed
+    /// header, or that don't send one in time, are rejected with
+    /// [AcceptError::ProxyProto] without affecting the rest of the tunnel.
+    pub fn proxy_header(&self) -> Option<&ProxyProtoHeader> {
+        self.info.proxy_header()
+    }
+
+    /// Get the TLS session negotiated with the client, if the tunnel
+    /// terminates TLS at the agent.
+    pub fn tls_info(&self) -> Option<&TlsInfo> {
+        self.info.tls_info()
+    }
+
+    // Strip the PROXY protocol header from the front of the connection.
+    pub(crate) async fn decode_proxy_proto(mut self) -> Result<Conn, AcceptError> {
+        let header = timeout(
+            PROXY_HEADER_TIMEOUT,
+            ProxyProtoHeader::read_from(&mut self.stream),
+        )
+        .await
+        .map_err(|_| ProxyProtoError::Io(io::ErrorKind::TimedOut))??;
+        if let Some(source) = header.source() {
+            self.info.remote_addr = Some(source);
+        }
+        self.info.proxy_header = Some(header);
+        Ok(self)
+    }
+
+    // Perform the TLS handshake with the client, so that the connection
+    // yields the decrypted stream.
+    pub(crate) async fn terminate_tls(
+        self,
+        config: Arc<ServerConfig>,
+        handshake_timeout: Duration,
+    ) -> Result<Conn, AcceptError> {
+        let Conn {
+            mut info, stream, ..
+        } = self;
+        let stream = timeout(
+            handshake_timeout,
+            TlsAcceptor::from(config).accept(stream.compat()),
+        )
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+        .map_err(|error| {
+            debug!(%error, remote_addr = %info.remote_addr(), "TLS handshake failed");
+            AcceptError::TlsHandshake(error.kind())
+        })?;
+        let (_, session) = stream.get_ref();
+        info.tls = Some(TlsInfo {
+            server_name: session.sni_hostname().map(String::from),
+            alpn_protocol: session.alpn_protocol().map(Vec::from),
+            peer_certificates: session.peer_certificates().unwrap_or_default().to_vec(),
+        });
+        Ok(Conn {
+            info,
+            stream: ConnStream::Tls(Box::new(stream.compat())),
+            peeked: Default::default(),
+        })
+    }
+
+    /// Wait for at least `len` bytes from the connection and return them
+    /// without consuming them, so that they're read again afterwards.
+    ///
+    /// Returns fewer than `len` bytes if the connection ends first.
+    pub async fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+        while self.peeked.len() < len {
+            if self.peek_more().await? == 0 {
+                break;
+            }
+        }
+        Ok(&self.peeked)
+    }
+
+    // Read whatever is available next into the peek buffer, returning how
+    // many bytes were read.
+    pub(crate) async fn peek_more(&mut self) -> io::Result<usize> {
+        self.stream.read_buf(&mut self.peeked).await
+    }
+
+    // The bytes that have been peeked so far.
+    pub(crate) fn peeked(&self) -> &[u8] {
+        &self.peeked
     }
 }
 
@@ -194,7 +601,12 @@ impl AsyncRead for Conn {
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut *self.stream).poll_read(cx, buf)
+        if !self.peeked.is_empty() {
+            let len = self.peeked.len().min(buf.remaining());
+            buf.put_slice(&self.peeked.split_to(len));
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.stream).poll_read(cx, buf)
     }
 }
 
@@ -204,19 +616,60 @@ impl AsyncWrite for Conn {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, std::io::Error>> {
-        Pin::new(&mut *self.stream).poll_write(cx, buf)
+        Pin::new(&mut self.stream).poll_write(cx, buf)
     }
     fn poll_flush(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
-        Pin::new(&mut *self.stream).poll_flush(cx)
+        Pin::new(&mut self.stream).poll_flush(cx)
     }
     fn poll_shutdown(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
-        Pin::new(&mut *self.stream).poll_shutdown(cx)
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for ConnStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(&mut **stream).poll_read(cx, buf),
+            ConnStream::Tls(stream) => Pin::new(&mut **stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ConnStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(&mut **stream).poll_write(cx, buf),
+            ConnStream::Tls(stream) => Pin::new(&mut **stream).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(&mut **stream).poll_flush(cx),
+            ConnStream::Tls(stream) => Pin::new(&mut **stream).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match self.get_mut() {
+            ConnStream::Plain(stream) => Pin::new(&mut **stream).poll_shutdown(cx),
+            ConnStream::Tls(stream) => Pin::new(&mut **stream).poll_shutdown(cx),
+        }
     }
 }
 
@@ -226,7 +679,14 @@ use axum::extract::connect_info::Connected;
 #[cfg(feature = "axum")]
 impl Connected<&Conn> for SocketAddr {
     fn connect_info(target: &Conn) -> Self {
-        target.remote_addr
+        target.remote_addr()
+    }
+}
+
+#[cfg(feature = "axum")]
+impl Connected<&Conn> for ConnInfo {
+    fn connect_info(target: &Conn) -> Self {
+        target.info.clone()
     }
 }
 
@@ -239,7 +699,7 @@ macro_rules! make_tunnel_type {
 
         #[async_trait]
         impl Tunnel for $wrapper {
-            fn id(&self) -> &str {
+            fn id(&self) -> String {
                 self.inner.id()
             }
 
@@ -247,6 +707,10 @@ macro_rules! make_tunnel_type {
                 self.inner.close().await
             }
 
+            async fn rebound(&mut self) -> Option<String> {
+                self.inner.rebound().await
+            }
+
             fn forwards_to(&self) -> &str {
                 self.inner.forwards_to()
             }
@@ -290,7 +754,7 @@ macro_rules! make_tunnel_type {
     };
     (url; $wrapper:ty) => {
         impl UrlTunnel for $wrapper {
-            fn url(&self) -> &str {
+            fn url(&self) -> String {
                 self.inner.url()
             }
         }
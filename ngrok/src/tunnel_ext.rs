@@ -8,6 +8,7 @@ use std::{
 use std::{
     io,
     net::SocketAddr,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
@@ -46,10 +47,55 @@ use tracing::{
 };
 
 use crate::{
+    config::ProxyProto,
     prelude::*,
+    proxy_proto::ProxyProtoHeader,
+    router::Router,
     Conn,
 };
 
+/// Options for forwarding tunnel connections to a local backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ForwardOptions {
+    proxy_proto: ProxyProto,
+}
+
+impl ForwardOptions {
+    /// Send a PROXY protocol header of the given version to the backend ahead
+    /// of each connection, so that it sees the address of the client that
+    /// connected to the ngrok edge.
+    ///
+    /// This is independent of the tunnel's own `proxy_proto` setting. The
+    /// destination address in the header is the one from the edge's PROXY
+    /// header when the tunnel decodes them. Otherwise, it's taken from the
+    /// tunnel's URL, with an unspecified IP unless the URL has one. If the
+    /// client's address isn't known, a v1 `UNKNOWN` or v2 `LOCAL` header is
+    /// sent instead.
+    pub fn proxy_proto(mut self, proxy_proto: ProxyProto) -> Self {
+        self.proxy_proto = proxy_proto;
+        self
+    }
+
+    // The header to send to the backend ahead of the connection, if any.
+    fn proxy_header(&self, conn: &Conn) -> Option<Vec<u8>> {
+        if self.proxy_proto == ProxyProto::None {
+            return None;
+        }
+        let info = conn.info();
+        let header = match info.remote_addr {
+            Some(source) => {
+                let destination = info
+                    .proxy_header()
+                    .and_then(|header| header.destination())
+                    .unwrap_or_else(|| info.edge_addr());
+                ProxyProtoHeader::new(self.proxy_proto, source, destination)
+            }
+            None => ProxyProtoHeader::unknown(self.proxy_proto),
+        };
+        Some(header.encode())
+    }
+}
+
 impl<T> TunnelExt for T where T: Tunnel {}
 
 /// Extension methods auto-implemented for all tunnel types
@@ -58,7 +104,18 @@ pub trait TunnelExt: Tunnel {
     /// Forward incoming tunnel connections to the provided TCP address.
     #[instrument(level = "debug", skip_all, fields(local_addrs))]
     async fn forward_tcp(&mut self, addr: impl ToSocketAddrs + Send) -> Result<(), io::Error> {
-        forward_conns(self, addr, |_, _| {}).await
+        forward_conns(self, addr, ForwardOptions::default(), |_, _| {}).await
+    }
+
+    /// Forward incoming tunnel connections to the provided TCP address, with
+    /// the given options.
+    #[instrument(level = "debug", skip_all, fields(local_addrs))]
+    async fn forward_tcp_with(
+        &mut self,
+        addr: impl ToSocketAddrs + Send,
+        options: ForwardOptions,
+    ) -> Result<(), io::Error> {
+        forward_conns(self, addr, options, |_, _| {}).await
     }
 
     /// Forward incoming tunnel connections to the provided TCP address.
@@ -67,18 +124,62 @@ pub trait TunnelExt: Tunnel {
     #[cfg(feature = "hyper")]
     #[instrument(level = "debug", skip_all, fields(local_addrs))]
     async fn forward_http(&mut self, addr: impl ToSocketAddrs + Send) -> Result<(), io::Error> {
-        forward_conns(self, addr, |e, c| drop(serve_gateway_error(e, c))).await
+        forward_conns(self, addr, ForwardOptions::default(), |e, c| {
+            drop(serve_gateway_error(e, c))
+        })
+        .await
     }
 
     /// Forward incoming tunnel connections to the provided Unix socket path.
     #[cfg(not(target_os = "windows"))]
     #[instrument(level = "debug", skip_all, fields(path))]
     async fn forward_unix(&mut self, addr: String) -> Result<(), io::Error> {
-        forward_unix_conns(self, addr, |_, _| {}).await
+        forward_unix_conns(self, addr, ForwardOptions::default(), |_, _| {}).await
+    }
+
+    /// Forward incoming tunnel connections to the provided Unix socket path,
+    /// with the given options.
+    #[cfg(not(target_os = "windows"))]
+    #[instrument(level = "debug", skip_all, fields(path))]
+    async fn forward_unix_with(
+        &mut self,
+        addr: String,
+        options: ForwardOptions,
+    ) -> Result<(), io::Error> {
+        forward_unix_conns(self, addr, options, |_, _| {}).await
+    }
+
+    /// Forward incoming tunnel connections to the backends chosen by the
+    /// [Router], based on what each connection starts with.
+    #[instrument(level = "debug", skip_all)]
+    async fn forward_routed(&mut self, router: Router) -> Result<(), io::Error> {
+        let router = Arc::new(router);
+        loop {
+            trace!("waiting for new tunnel connection");
+            let tunnel_conn = match self.try_next().await {
+                Ok(Some(conn)) => conn,
+                Ok(None) => break,
+                // Only that connection failed, so keep forwarding the rest.
+                Err(error) if error.is_per_connection() => {
+                    warn!(%error, "dropping tunnel connection");
+                    continue;
+                }
+                Err(error) => return Err(io::Error::new(io::ErrorKind::NotConnected, error)),
+            };
+            trace!(remote_addr = %tunnel_conn.remote_addr(), "accepted tunnel connection");
+            router.spawn_dispatch(tunnel_conn);
+        }
+        debug!("listener closed, exiting");
+        Ok(())
     }
 }
 
-async fn forward_conns<T, A, F>(this: &mut T, addr: A, mut on_err: F) -> Result<(), io::Error>
+async fn forward_conns<T, A, F>(
+    this: &mut T,
+    addr: A,
+    options: ForwardOptions,
+    mut on_err: F,
+) -> Result<(), io::Error>
 where
     T: Tunnel + ?Sized,
     A: ToSocketAddrs,
@@ -90,7 +191,7 @@ where
     trace!("looked up local addrs");
     loop {
         trace!("waiting for new tunnel connection");
-        if !handle_one(this, addrs.as_slice(), &mut on_err).await? {
+        if !handle_one(this, addrs.as_slice(), &options, &mut on_err).await? {
             debug!("listener closed, exiting");
             break;
         }
@@ -102,6 +203,7 @@ where
 async fn forward_unix_conns<T, F>(
     this: &mut T,
     addr: String,
+    options: ForwardOptions,
     mut on_err: F,
 ) -> Result<(), io::Error>
 where
@@ -113,7 +215,7 @@ where
     span.record("path", field::debug(&path));
     loop {
         trace!("waiting for new tunnel connection");
-        if !handle_one_unix(this, path, &mut on_err).await? {
+        if !handle_one_unix(this, path, &options, &mut on_err).await? {
             debug!("listener closed, exiting");
             break;
         }
@@ -137,7 +239,7 @@ async fn forward_bytes(
     Ok(())
 }
 
-fn join_streams(
+pub(crate) fn join_streams(
     left: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
     right: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
 ) -> JoinHandle<()> {
@@ -158,6 +260,7 @@ fn join_streams(
 async fn handle_one<T, F>(
     this: &mut T,
     addrs: &[SocketAddr],
+    options: &ForwardOptions,
     on_error: F,
 ) -> Result<bool, io::Error>
 where
@@ -165,21 +268,22 @@ where
     F: FnOnce(io::Error, Conn),
 {
     let span = Span::current();
-    let tunnel_conn = if let Some(conn) = this
-        .try_next()
-        .await
-        .map_err(|err| io::Error::new(io::ErrorKind::NotConnected, err))?
-    {
-        conn
-    } else {
-        return Ok(false);
+    let tunnel_conn = match this.try_next().await {
+        Ok(Some(conn)) => conn,
+        Ok(None) => return Ok(false),
+        // Only that connection failed, so keep forwarding the rest.
+        Err(error) if error.is_per_connection() => {
+            warn!(%error, "dropping tunnel connection");
+            return Ok(true);
+        }
+        Err(error) => return Err(io::Error::new(io::ErrorKind::NotConnected, error)),
     };
 
     span.record("remote_addr", field::debug(tunnel_conn.remote_addr()));
 
     trace!("accepted tunnel connection");
 
-    let local_conn = match TcpStream::connect(addrs).await {
+    let mut local_conn = match TcpStream::connect(addrs).await {
         Ok(conn) => conn,
         Err(error) => {
             warn!(%error, "error establishing local connection");
@@ -191,6 +295,11 @@ where
     };
     span.record("local_addr", field::debug(local_conn.peer_addr().unwrap()));
 
+    if let Err(error) = write_proxy_header(&mut local_conn, &tunnel_conn, options).await {
+        warn!(%error, "error writing PROXY header to local connection");
+        return Ok(true);
+    }
+
     debug!("established local connection, joining streams");
 
     join_streams(tunnel_conn, local_conn);
@@ -199,27 +308,33 @@ where
 
 #[cfg(not(target_os = "windows"))]
 #[instrument(level = "debug", skip_all, fields(remote_addr, local_addr))]
-async fn handle_one_unix<T, F>(this: &mut T, addr: &Path, on_error: F) -> Result<bool, io::Error>
+async fn handle_one_unix<T, F>(
+    this: &mut T,
+    addr: &Path,
+    options: &ForwardOptions,
+    on_error: F,
+) -> Result<bool, io::Error>
 where
     T: Tunnel + ?Sized,
     F: FnOnce(io::Error, Conn),
 {
     let span = Span::current();
-    let tunnel_conn = if let Some(conn) = this
-        .try_next()
-        .await
-        .map_err(|err| io::Error::new(io::ErrorKind::NotConnected, err))?
-    {
-        conn
-    } else {
-        return Ok(false);
+    let tunnel_conn = match this.try_next().await {
+        Ok(Some(conn)) => conn,
+        Ok(None) => return Ok(false),
+        // Only that connection failed, so keep forwarding the rest.
+        Err(error) if error.is_per_connection() => {
+            warn!(%error, "dropping tunnel connection");
+            return Ok(true);
+        }
+        Err(error) => return Err(io::Error::new(io::ErrorKind::NotConnected, error)),
     };
 
     span.record("remote_addr", field::debug(tunnel_conn.remote_addr()));
 
     trace!("accepted tunnel connection");
 
-    let local_conn = match UnixStream::connect(addr).await {
+    let mut local_conn = match UnixStream::connect(addr).await {
         Ok(conn) => conn,
         Err(error) => {
             warn!(%error, "error establishing local unix connection");
@@ -231,12 +346,28 @@ where
     };
     span.record("local_addr", field::debug(local_conn.peer_addr().unwrap()));
 
+    if let Err(error) = write_proxy_header(&mut local_conn, &tunnel_conn, options).await {
+        warn!(%error, "error writing PROXY header to local connection");
+        return Ok(true);
+    }
+
     debug!("established local connection, joining streams");
 
     join_streams(tunnel_conn, local_conn);
     Ok(true)
 }
 
+pub(crate) async fn write_proxy_header(
+    local_conn: &mut (impl AsyncWrite + Unpin),
+    tunnel_conn: &Conn,
+    options: &ForwardOptions,
+) -> Result<(), io::Error> {
+    match options.proxy_header(tunnel_conn) {
+        Some(header) => local_conn.write_all(&header).await,
+        None => Ok(()),
+    }
+}
+
 #[cfg(feature = "hyper")]
 #[allow(dead_code)]
 fn serve_gateway_error(